@@ -0,0 +1,27 @@
+use super::*;
+
+/// `combine` must be associative with `identity()` as its neutral element.
+pub trait ForestAggregate: ForestNodeContent {
+    type Summary: Clone + 'static;
+    fn identity() -> Self::Summary;
+    fn summarize(&self) -> Self::Summary;
+    fn combine(summary: &mut Self::Summary, other: &Self::Summary);
+}
+
+impl<T: ForestAggregate> ForestNode<T> {
+    /// Aggregates are computed lazily. A change only drops the cached summaries on the
+    /// path to its root, so the next read recomputes those nodes from their own summary
+    /// and the cached summaries of their children.
+    pub fn aggregate(&self) -> T::Summary {
+        if let Some(ref x) = *self.aggregate.borrow() {
+            return x.downcast_ref::<T::Summary>().unwrap().clone();
+        }
+        let mut ret = T::identity();
+        T::combine(&mut ret, &self.summarize());
+        for child in self.iter_children() {
+            T::combine(&mut ret, &child.aggregate());
+        }
+        *self.aggregate.borrow_mut() = Some(Box::new(ret.clone()));
+        ret
+    }
+}
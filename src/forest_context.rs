@@ -1,20 +1,208 @@
 use std::rc::Rc;
-use std::cell::UnsafeCell;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::{HashMap, VecDeque};
+use super::*;
 
-pub(crate) struct ForestContext {
+pub(crate) struct ForestContext<T: ForestNodeContent> {
     is_mut: UnsafeCell<bool>,
     count: UnsafeCell<usize>,
+    child_storage: Cell<ForestChildStorage>,
+    node_count: Cell<usize>,
+    registry: RefCell<Option<HashMap<ForestNodePtr<T>, ForestNodeWeak<T>>>>,
+    roots: RefCell<Vec<ForestNodeRc<T>>>,
+    deferred: Cell<bool>,
+    notifications: RefCell<VecDeque<ForestNotification<T>>>,
+    observers: RefCell<Vec<ForestObserverWeak<T>>>,
+    undo: RefCell<Option<ForestUndoWeak<T>>>,
+    transactions: RefCell<Vec<Vec<ForestUndoOp<T>>>>,
+    inherited: RefCell<Vec<ForestInheritedWeak<T>>>,
+    updates: RefCell<Vec<ForestNodeWeak<T>>>,
+    indexes: RefCell<Vec<ForestIndexWeak<T>>>,
 }
 
-impl ForestContext {
+impl<T: ForestNodeContent> ForestContext<T> {
     pub(crate) fn new() -> Self {
         Self {
             is_mut: UnsafeCell::new(false),
             count: UnsafeCell::new(0),
+            child_storage: Cell::new(ForestChildStorage::default()),
+            node_count: Cell::new(0),
+            registry: RefCell::new(None),
+            roots: RefCell::new(vec![]),
+            deferred: Cell::new(false),
+            notifications: RefCell::new(VecDeque::new()),
+            observers: RefCell::new(vec![]),
+            undo: RefCell::new(None),
+            transactions: RefCell::new(vec![]),
+            inherited: RefCell::new(vec![]),
+            updates: RefCell::new(vec![]),
+            indexes: RefCell::new(vec![]),
         }
     }
     #[inline]
-    pub(crate) fn borrow(s: &Rc<Self>) -> ForestContextRef {
+    pub(crate) fn child_storage(&self) -> ForestChildStorage {
+        self.child_storage.get()
+    }
+    #[inline]
+    pub(crate) fn set_child_storage(&self, storage: ForestChildStorage) {
+        self.child_storage.set(storage)
+    }
+    #[inline]
+    pub(crate) fn node_count(&self) -> usize {
+        self.node_count.get()
+    }
+    pub(crate) fn enable_registry(&self) {
+        if self.node_count.get() > 0 { panic!("Node registry should be enabled before any node is created") }
+        let mut registry = self.registry.borrow_mut();
+        if registry.is_none() {
+            *registry = Some(HashMap::new());
+        }
+    }
+    pub(crate) fn has_registry(&self) -> bool {
+        self.registry.borrow().is_some()
+    }
+    pub(crate) fn registered_nodes(&self) -> Vec<ForestNodeRc<T>> {
+        match *self.registry.borrow() {
+            None => panic!("Node registry is not enabled for this Forest"),
+            Some(ref registry) => registry.values().filter_map(|x| x.upgrade()).collect(),
+        }
+    }
+    pub(crate) fn roots(&self) -> Vec<ForestNodeRc<T>> {
+        self.roots.borrow().clone()
+    }
+    pub(crate) fn is_root(&self, ptr: ForestNodePtr<T>) -> bool {
+        self.roots.borrow().iter().any(|x| x.as_ptr() == ptr)
+    }
+    pub(crate) fn add_root(&self, node: ForestNodeRc<T>) -> bool {
+        if self.is_root(node.as_ptr()) { return false }
+        self.roots.borrow_mut().push(node);
+        true
+    }
+    pub(crate) fn remove_root(&self, ptr: ForestNodePtr<T>) -> Option<ForestNodeRc<T>> {
+        let mut roots = self.roots.borrow_mut();
+        let i = roots.iter().position(|x| x.as_ptr() == ptr)?;
+        Some(roots.remove(i))
+    }
+    pub(crate) fn take_roots(&self) -> Vec<ForestNodeRc<T>> {
+        std::mem::take(&mut *self.roots.borrow_mut())
+    }
+    #[inline]
+    pub(crate) fn is_deferred(&self) -> bool {
+        self.deferred.get()
+    }
+    #[inline]
+    pub(crate) fn set_deferred(&self, deferred: bool) {
+        self.deferred.set(deferred)
+    }
+    #[inline]
+    pub(crate) fn has_notifications(&self) -> bool {
+        !self.notifications.borrow().is_empty()
+    }
+    #[inline]
+    pub(crate) fn push_notification(&self, notification: ForestNotification<T>) {
+        self.notifications.borrow_mut().push_back(notification);
+    }
+    #[inline]
+    pub(crate) fn pop_notification(&self) -> Option<ForestNotification<T>> {
+        self.notifications.borrow_mut().pop_front()
+    }
+    pub(crate) fn take_notifications(&self) -> VecDeque<ForestNotification<T>> {
+        std::mem::take(&mut *self.notifications.borrow_mut())
+    }
+    pub(crate) fn add_observer(&self, observer: ForestObserverWeak<T>) {
+        self.observers.borrow_mut().push(observer);
+    }
+    pub(crate) fn has_observers(&self) -> bool {
+        let mut observers = self.observers.borrow_mut();
+        observers.retain(|x| x.strong_count() > 0);
+        !observers.is_empty()
+    }
+    pub(crate) fn observers(&self) -> Vec<Rc<ForestObserverInner<T>>> {
+        self.observers.borrow().iter().filter_map(|x| x.upgrade()).collect()
+    }
+    pub(crate) fn set_undo(&self, undo: Option<ForestUndoWeak<T>>) {
+        *self.undo.borrow_mut() = undo;
+    }
+    #[inline]
+    pub(crate) fn undo(&self) -> Option<Rc<ForestUndoInner<T>>> {
+        self.undo.borrow().as_ref().and_then(|x| x.upgrade())
+    }
+    #[inline]
+    pub(crate) fn is_recording(&self) -> bool {
+        !self.transactions.borrow().is_empty() || self.undo().is_some()
+    }
+    pub(crate) fn record_op(&self, op: ForestUndoOp<T>) {
+        if let Some(journal) = self.transactions.borrow_mut().last_mut() {
+            if let Some(op) = op.clone_structural() {
+                journal.push(op);
+            }
+        }
+        if let Some(u) = self.undo() {
+            u.record(op);
+        }
+    }
+    pub(crate) fn begin_transaction(&self) {
+        self.transactions.borrow_mut().push(vec![]);
+    }
+    pub(crate) fn end_transaction(&self) -> Vec<ForestUndoOp<T>> {
+        self.transactions.borrow_mut().pop().unwrap()
+    }
+    pub(crate) fn commit_transaction(&self, journal: Vec<ForestUndoOp<T>>) {
+        if let Some(outer) = self.transactions.borrow_mut().last_mut() {
+            outer.extend(journal);
+        }
+    }
+    pub(crate) fn add_index(&self, index: ForestIndexWeak<T>) {
+        self.indexes.borrow_mut().push(index);
+    }
+    #[inline]
+    pub(crate) fn has_indexes(&self) -> bool {
+        !self.indexes.borrow().is_empty()
+    }
+    pub(crate) fn indexes(&self) -> Vec<Rc<RefCell<dyn ForestIndexDyn<T>>>> {
+        let mut indexes = self.indexes.borrow_mut();
+        indexes.retain(|x| x.strong_count() > 0);
+        indexes.iter().filter_map(|x| x.upgrade()).collect()
+    }
+    pub(crate) fn push_update(&self, node: ForestNodeWeak<T>) {
+        self.updates.borrow_mut().push(node);
+    }
+    pub(crate) fn take_updates(&self) -> Vec<ForestNodeWeak<T>> {
+        std::mem::take(&mut *self.updates.borrow_mut())
+    }
+    pub(crate) fn add_inherited(&self, inherited: ForestInheritedWeak<T>) {
+        self.inherited.borrow_mut().push(inherited);
+    }
+    pub(crate) fn inherited(&self) -> Vec<Rc<dyn ForestInheritedCache<T>>> {
+        let mut inherited = self.inherited.borrow_mut();
+        inherited.retain(|x| x.strong_count() > 0);
+        inherited.iter().filter_map(|x| x.upgrade()).collect()
+    }
+    #[inline]
+    pub(crate) fn node_created(&self) {
+        self.node_count.set(self.node_count.get() + 1);
+    }
+    #[inline]
+    pub(crate) fn node_associated(&self, weak: &ForestNodeWeak<T>) {
+        if let Some(ref mut registry) = *self.registry.borrow_mut() {
+            registry.insert(weak.as_ptr(), weak.clone());
+        }
+    }
+    #[inline]
+    pub(crate) fn node_dropped(&self, ptr: Option<ForestNodePtr<T>>) {
+        self.node_count.set(self.node_count.get() - 1);
+        if let Some(ptr) = ptr {
+            if let Some(ref mut registry) = *self.registry.borrow_mut() {
+                registry.remove(&ptr);
+            }
+        }
+    }
+    #[inline]
+    pub(crate) fn is_borrowed_mut(&self) -> bool {
+        unsafe { *self.is_mut.get() }
+    }
+    #[inline]
+    pub(crate) fn borrow(s: &Rc<Self>) -> ForestContextRef<T> {
         unsafe {
             if *s.is_mut.get() || *s.count.get() > 0 { panic!("Forest has been borrowed") };
             *s.count.get() += 1;
@@ -22,7 +210,7 @@ impl ForestContext {
         ForestContextRef::new(s.clone())
     }
     #[inline]
-    pub(crate) fn try_borrow(s: &Rc<Self>) -> Result<ForestContextRef, ()> {
+    pub(crate) fn try_borrow(s: &Rc<Self>) -> Result<ForestContextRef<T>, ()> {
         unsafe {
             if *s.is_mut.get() || *s.count.get() > 0 {
                 return Err(());
@@ -32,7 +220,7 @@ impl ForestContext {
         Ok(ForestContextRef::new(s.clone()))
     }
     #[inline]
-    pub(crate) fn borrow_mut(s: &Rc<Self>) -> ForestContextRefMut {
+    pub(crate) fn borrow_mut(s: &Rc<Self>) -> ForestContextRefMut<T> {
         unsafe {
             if *s.count.get() > 0 { panic!("Forest has been borrowed") };
             *s.is_mut.get() = true;
@@ -41,7 +229,7 @@ impl ForestContext {
         ForestContextRefMut::new(s.clone())
     }
     #[inline]
-    pub(crate) fn try_borrow_mut(s: &Rc<Self>) -> Result<ForestContextRefMut, ()> {
+    pub(crate) fn try_borrow_mut(s: &Rc<Self>) -> Result<ForestContextRefMut<T>, ()> {
         unsafe {
             if *s.count.get() > 0 {
                 return Err(());
@@ -53,19 +241,19 @@ impl ForestContext {
     }
 }
 
-pub(crate) struct ForestContextRef {
-    context: Rc<ForestContext>,
+pub(crate) struct ForestContextRef<T: ForestNodeContent> {
+    context: Rc<ForestContext<T>>,
 }
 
-impl ForestContextRef {
-    pub(crate) fn new(context: Rc<ForestContext>) -> Self {
+impl<T: ForestNodeContent> ForestContextRef<T> {
+    pub(crate) fn new(context: Rc<ForestContext<T>>) -> Self {
         Self {
             context
         }
     }
 }
 
-impl Drop for ForestContextRef {
+impl<T: ForestNodeContent> Drop for ForestContextRef<T> {
     fn drop(&mut self) {
         let c = self.context.count.get();
         unsafe {
@@ -77,19 +265,19 @@ impl Drop for ForestContextRef {
     }
 }
 
-pub(crate) struct ForestContextRefMut {
-    context: Rc<ForestContext>,
+pub(crate) struct ForestContextRefMut<T: ForestNodeContent> {
+    context: Rc<ForestContext<T>>,
 }
 
-impl ForestContextRefMut {
-    pub(crate) fn new(context: Rc<ForestContext>) -> Self {
+impl<T: ForestNodeContent> ForestContextRefMut<T> {
+    pub(crate) fn new(context: Rc<ForestContext<T>>) -> Self {
         Self {
             context
         }
     }
 }
 
-impl<'a> Drop for ForestContextRefMut {
+impl<T: ForestNodeContent> Drop for ForestContextRefMut<T> {
     fn drop(&mut self) {
         let c = self.context.count.get();
         unsafe {
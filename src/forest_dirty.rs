@@ -0,0 +1,47 @@
+use super::*;
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    #[inline]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+    #[inline]
+    pub fn is_subtree_dirty(&self) -> bool {
+        self.subtree_dirty
+    }
+    pub(crate) fn mark_dirty(&mut self) {
+        self.revision += 1;
+        self.dirty = true;
+        if self.subtree_dirty { return }
+        self.subtree_dirty = true;
+        let mut cur = self.parent.as_ref().and_then(|x| x.upgrade());
+        while let Some(rc) = cur {
+            let n = rc.deref_mut_with(self);
+            if n.subtree_dirty { break }
+            n.subtree_dirty = true;
+            cur = n.parent.as_ref().and_then(|x| x.upgrade());
+        }
+    }
+    pub fn take_dirty_descendants(&mut self) -> Vec<ForestNodeRc<T>> {
+        let mut ret = vec![];
+        if !self.subtree_dirty { return ret }
+        let mut stack = vec![self.rc()];
+        while let Some(rc) = stack.pop() {
+            let n = rc.deref_mut_with(self);
+            if !n.subtree_dirty { continue }
+            n.subtree_dirty = false;
+            let len = stack.len();
+            stack.extend(n.children.iter().cloned());
+            stack[len..].reverse();
+            if n.dirty {
+                n.dirty = false;
+                ret.push(rc);
+            }
+        }
+        ret
+    }
+}
@@ -0,0 +1,84 @@
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForestEventPhase {
+    Capture,
+    Target,
+    Bubble,
+}
+
+pub struct ForestEvent<T: ForestNodeContent, E> {
+    pub detail: E,
+    target: ForestNodeRc<T>,
+    current_target: ForestNodeRc<T>,
+    phase: ForestEventPhase,
+    propagation_stopped: bool,
+    default_prevented: bool,
+}
+
+impl<T: ForestNodeContent, E> ForestEvent<T, E> {
+    #[inline]
+    pub fn target(&self) -> &ForestNodeRc<T> {
+        &self.target
+    }
+    #[inline]
+    pub fn current_target(&self) -> &ForestNodeRc<T> {
+        &self.current_target
+    }
+    #[inline]
+    pub fn phase(&self) -> ForestEventPhase {
+        self.phase
+    }
+    #[inline]
+    pub fn stop_propagation(&mut self) {
+        self.propagation_stopped = true;
+    }
+    #[inline]
+    pub fn propagation_stopped(&self) -> bool {
+        self.propagation_stopped
+    }
+    #[inline]
+    pub fn prevent_default(&mut self) {
+        self.default_prevented = true;
+    }
+    #[inline]
+    pub fn default_prevented(&self) -> bool {
+        self.default_prevented
+    }
+}
+
+pub trait ForestEventHandler<E>: ForestNodeContent {
+    #[inline]
+    fn handle_event(&mut self, _event: &mut ForestEvent<Self, E>) where Self: Sized { }
+}
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    pub fn dispatch_event<E>(&mut self, detail: E) -> ForestEvent<T, E> where T: ForestEventHandler<E> {
+        let target = self.rc();
+        let mut path = vec![];
+        let mut cur = self.parent();
+        while let Some(p) = cur {
+            path.push(p.rc());
+            cur = p.parent();
+        }
+        let mut event = ForestEvent {
+            detail,
+            target: target.clone(),
+            current_target: target.clone(),
+            phase: ForestEventPhase::Capture,
+            propagation_stopped: false,
+            default_prevented: false,
+        };
+        let steps = path.iter().rev().map(|x| (x, ForestEventPhase::Capture))
+            .chain(std::iter::once((&target, ForestEventPhase::Target)))
+            .chain(path.iter().map(|x| (x, ForestEventPhase::Bubble)));
+        for (node, phase) in steps {
+            event.current_target = node.clone();
+            event.phase = phase;
+            node.deref_mut_with(self).handle_event(&mut event);
+            if event.propagation_stopped { break }
+        }
+        event.current_target = target;
+        event
+    }
+}
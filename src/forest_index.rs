@@ -0,0 +1,81 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Weak;
+use super::*;
+
+pub(crate) trait ForestIndexDyn<T: ForestNodeContent> {
+    fn insert(&mut self, node: &ForestNode<T>);
+    fn remove(&mut self, node: &ForestNode<T>);
+}
+
+pub(crate) type ForestIndexWeak<T> = Weak<RefCell<dyn ForestIndexDyn<T>>>;
+
+type KeyFn<T, K> = Box<dyn Fn(&T) -> Option<K>>;
+
+struct ForestIndexInner<T: ForestNodeContent, K> {
+    key: KeyFn<T, K>,
+    nodes: HashMap<K, Vec<ForestNodeWeak<T>>>,
+    keys: HashMap<ForestNodePtr<T>, K>,
+}
+
+impl<T: ForestNodeContent, K: Hash + Eq + Clone> ForestIndexDyn<T> for ForestIndexInner<T, K> {
+    fn insert(&mut self, node: &ForestNode<T>) {
+        let Some(key) = (self.key)(node) else { return };
+        let weak = node.self_weak.clone().unwrap();
+        self.keys.insert(weak.as_ptr(), key.clone());
+        self.nodes.entry(key).or_default().push(weak);
+    }
+    fn remove(&mut self, node: &ForestNode<T>) {
+        let ptr = node.self_weak.as_ref().unwrap().as_ptr();
+        let Some(key) = self.keys.remove(&ptr) else { return };
+        let Some(nodes) = self.nodes.get_mut(&key) else { return };
+        nodes.retain(|x| x.as_ptr() != ptr);
+        if nodes.is_empty() {
+            self.nodes.remove(&key);
+        }
+    }
+}
+
+/// Indexes only the nodes that are connected to a root of the forest.
+pub struct ForestIndex<T: ForestNodeContent, K> {
+    inner: Rc<RefCell<ForestIndexInner<T, K>>>,
+    context: Weak<ForestContext<T>>,
+}
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    pub(crate) fn update_indexes(&self, connected: bool) {
+        if !self.context.has_indexes() { return }
+        for index in self.context.indexes() {
+            let mut index = index.borrow_mut();
+            index.remove(self);
+            if connected {
+                index.insert(self);
+            }
+        }
+    }
+}
+
+impl<T: ForestNodeContent + 'static> Forest<T> {
+    pub fn add_index<K: Hash + Eq + Clone + 'static, F: Fn(&T) -> Option<K> + 'static>(&mut self, key: F) -> ForestIndex<T, K> {
+        let _status = ForestContext::borrow_mut(&self.context);
+        let mut inner = ForestIndexInner { key: Box::new(key), nodes: HashMap::new(), keys: HashMap::new() };
+        let mut stack = self.context.roots();
+        while let Some(rc) = stack.pop() {
+            let n = unsafe { rc.forest_node() };
+            inner.insert(n);
+            stack.extend(n.iter().cloned());
+        }
+        let inner = Rc::new(RefCell::new(inner));
+        let weak: ForestIndexWeak<T> = Rc::downgrade(&(inner.clone() as Rc<RefCell<dyn ForestIndexDyn<T>>>));
+        self.context.add_index(weak);
+        ForestIndex { inner, context: Rc::downgrade(&self.context) }
+    }
+    pub fn lookup<K: Hash + Eq + Clone + 'static>(&self, index: &ForestIndex<T, K>, key: &K) -> Option<ForestNodeRc<T>> {
+        if index.context.as_ptr() != Rc::as_ptr(&self.context) {
+            panic!("A ForestIndex can only be used with its Forest");
+        }
+        let _status = ForestContext::borrow(&self.context);
+        index.inner.borrow().nodes.get(key)?.iter().find_map(|x| x.upgrade())
+    }
+}
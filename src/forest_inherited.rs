@@ -0,0 +1,92 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Weak;
+use super::*;
+
+pub(crate) trait ForestInheritedCache<T: ForestNodeContent> {
+    fn invalidate(&self, node: &ForestNode<T>);
+}
+
+pub(crate) type ForestInheritedWeak<T> = Weak<dyn ForestInheritedCache<T>>;
+
+type OwnValueFn<T, V> = Box<dyn Fn(&T) -> Option<V>>;
+
+type InheritedMap<T, V> = HashMap<ForestNodePtr<T>, (ForestNodeWeak<T>, V)>;
+
+struct ForestInheritedInner<T: ForestNodeContent, V> {
+    own_value: OwnValueFn<T, V>,
+    default: V,
+    cache: RefCell<InheritedMap<T, V>>,
+    cleanup_size: Cell<usize>,
+}
+
+impl<T: ForestNodeContent, V> ForestInheritedCache<T> for ForestInheritedInner<T, V> {
+    fn invalidate(&self, node: &ForestNode<T>) {
+        let mut cache = self.cache.borrow_mut();
+        if cache.remove(&node.self_weak.as_ref().unwrap().as_ptr()).is_none() { return }
+        let mut stack: Vec<&ForestNode<T>> = vec![node];
+        while let Some(n) = stack.pop() {
+            for child in n.iter() {
+                if cache.remove(&child.as_ptr()).is_some() {
+                    stack.push(child.deref_with(n));
+                }
+            }
+        }
+    }
+}
+
+pub struct ForestInherited<T: ForestNodeContent, V> {
+    inner: Rc<ForestInheritedInner<T, V>>,
+}
+
+impl<T: ForestNodeContent + 'static, V: Clone + 'static> ForestInherited<T, V> {
+    pub fn new<F: Fn(&T) -> Option<V> + 'static>(forest: &mut Forest<T>, default: V, own_value: F) -> Self {
+        ForestContext::borrow_mut(&forest.context);
+        let inner = Rc::new(ForestInheritedInner {
+            own_value: Box::new(own_value),
+            default,
+            cache: RefCell::new(HashMap::new()),
+            cleanup_size: Cell::new(64),
+        });
+        let weak: ForestInheritedWeak<T> = Rc::downgrade(&(inner.clone() as Rc<dyn ForestInheritedCache<T>>));
+        forest.context.add_inherited(weak);
+        Self { inner }
+    }
+    pub fn get(&self, node: &ForestNode<T>) -> V {
+        let mut cache = self.inner.cache.borrow_mut();
+        let mut path: Vec<&ForestNode<T>> = vec![];
+        let mut cur = Some(node);
+        let value = loop {
+            match cur {
+                None => break self.inner.default.clone(),
+                Some(n) => {
+                    if let Some((_, v)) = cache.get(&n.self_weak.as_ref().unwrap().as_ptr()) {
+                        break v.clone();
+                    }
+                    path.push(n);
+                    if let Some(v) = (self.inner.own_value)(n) {
+                        break v;
+                    }
+                    cur = n.parent();
+                },
+            }
+        };
+        for n in path {
+            let weak = n.self_weak.clone().unwrap();
+            cache.insert(weak.as_ptr(), (weak, value.clone()));
+        }
+        if cache.len() > self.inner.cleanup_size.get() {
+            cache.retain(|_, (weak, _)| weak.upgrade().is_some());
+            self.inner.cleanup_size.set(cache.len() * 2 + 64);
+        }
+        value
+    }
+}
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    pub(crate) fn invalidate_inherited(&self) {
+        for cache in self.context.inherited() {
+            cache.invalidate(self);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use super::*;
+
+pub struct ForestLeakedCycle<T: ForestNodeContent> {
+    pub cycle: Vec<ForestNodeRc<T>>,
+    pub dependents: Vec<ForestNodeRc<T>>,
+}
+
+impl<T: ForestNodeContent> Forest<T> {
+    pub fn find_leaked_cycles(&self) -> Vec<ForestLeakedCycle<T>> {
+        let _status = ForestContext::borrow(&self.context);
+        let nodes = self.context.registered_nodes();
+        let index: HashMap<ForestNodePtr<T>, usize> = nodes.iter().enumerate().map(|(i, x)| (x.as_ptr(), i)).collect();
+
+        // count the strong references which are not held by other nodes
+        let mut external: Vec<isize> = nodes.iter().map(|x| x.strong_count() as isize - 1).collect();
+        let mut edges: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            let n = unsafe { node.forest_node() };
+            let mut add_edge = |rc: &ForestNodeRc<T>| {
+                if let Some(&j) = index.get(&rc.as_ptr()) {
+                    external[j] -= 1;
+                    edges[i].push(j);
+                }
+            };
+            for child in n.iter() {
+                add_edge(child);
+            }
+            n.trace_node_rcs(&mut add_edge);
+        }
+
+        // nodes reachable from external references are alive
+        let mut alive = vec![false; nodes.len()];
+        let mut stack: Vec<usize> = (0..nodes.len()).filter(|&i| external[i] > 0).collect();
+        while let Some(i) = stack.pop() {
+            if alive[i] { continue }
+            alive[i] = true;
+            stack.extend(edges[i].iter().filter(|&&j| !alive[j]));
+        }
+
+        // strongly connected components of the remaining nodes, found with Tarjan's algorithm
+        let mut order: Vec<Option<usize>> = vec![None; nodes.len()];
+        let mut low = vec![0; nodes.len()];
+        let mut on_stack = vec![false; nodes.len()];
+        let mut component: Vec<usize> = vec![];
+        let mut cycles: Vec<Vec<usize>> = vec![];
+        let mut next = 0;
+        for start in 0..nodes.len() {
+            if alive[start] || order[start].is_some() { continue }
+            let mut calls: Vec<(usize, usize)> = vec![(start, 0)];
+            order[start] = Some(next);
+            low[start] = next;
+            next += 1;
+            component.push(start);
+            on_stack[start] = true;
+            while let Some(&mut (i, ref mut edge)) = calls.last_mut() {
+                if let Some(&j) = edges[i].get(*edge) {
+                    *edge += 1;
+                    if alive[j] { continue }
+                    match order[j] {
+                        None => {
+                            order[j] = Some(next);
+                            low[j] = next;
+                            next += 1;
+                            component.push(j);
+                            on_stack[j] = true;
+                            calls.push((j, 0));
+                        },
+                        Some(o) if on_stack[j] => low[i] = low[i].min(o),
+                        Some(_) => { },
+                    }
+                    continue
+                }
+                calls.pop();
+                if let Some(&(parent, _)) = calls.last() {
+                    low[parent] = low[parent].min(low[i]);
+                }
+                if Some(low[i]) != order[i] { continue }
+                let at = component.iter().rposition(|&x| x == i).unwrap();
+                let members = component.split_off(at);
+                for &m in members.iter() {
+                    on_stack[m] = false;
+                }
+                if members.len() > 1 || edges[i].contains(&i) {
+                    cycles.push(members);
+                }
+            }
+        }
+
+        // the other leaked nodes are kept alive by the first cycle that reaches them
+        let mut claimed = vec![false; nodes.len()];
+        for cycle in cycles.iter() {
+            for &i in cycle {
+                claimed[i] = true;
+            }
+        }
+        cycles.into_iter().map(|cycle| {
+            let mut dependents = vec![];
+            let mut stack: Vec<usize> = cycle.iter().flat_map(|&i| edges[i].iter().cloned()).collect();
+            while let Some(i) = stack.pop() {
+                if alive[i] || claimed[i] { continue }
+                claimed[i] = true;
+                dependents.push(nodes[i].clone());
+                stack.extend(edges[i].iter().cloned());
+            }
+            ForestLeakedCycle {
+                cycle: cycle.into_iter().map(|i| nodes[i].clone()).collect(),
+                dependents,
+            }
+        }).collect()
+    }
+}
@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+use std::rc::Weak;
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationRecordKind {
+    ChildList,
+    Content,
+}
+
+pub struct MutationRecord<T: ForestNodeContent> {
+    pub kind: MutationRecordKind,
+    pub target: ForestNodeRc<T>,
+    pub added_nodes: Vec<ForestNodeRc<T>>,
+    pub removed_nodes: Vec<ForestNodeRc<T>>,
+    pub previous_sibling: Option<ForestNodeRc<T>>,
+    pub next_sibling: Option<ForestNodeRc<T>>,
+}
+
+impl<T: ForestNodeContent> Clone for MutationRecord<T> {
+    fn clone(&self) -> Self {
+        Self {
+            kind: self.kind,
+            target: self.target.clone(),
+            added_nodes: self.added_nodes.clone(),
+            removed_nodes: self.removed_nodes.clone(),
+            previous_sibling: self.previous_sibling.clone(),
+            next_sibling: self.next_sibling.clone(),
+        }
+    }
+}
+
+pub(crate) struct ForestObserverInner<T: ForestNodeContent> {
+    target: ForestNodeWeak<T>,
+    subtree: bool,
+    records: RefCell<Vec<MutationRecord<T>>>,
+}
+
+impl<T: ForestNodeContent> ForestObserverInner<T> {
+    fn observes(&self, node: &ForestNode<T>) -> bool {
+        let target = self.target.as_ptr();
+        let mut cur = node;
+        loop {
+            if cur.self_weak.as_ref().unwrap().as_ptr() == target { return true }
+            if !self.subtree { return false }
+            match cur.parent() {
+                None => return false,
+                Some(p) => cur = p,
+            }
+        }
+    }
+}
+
+pub struct ForestMutationObserver<T: ForestNodeContent> {
+    inner: Rc<ForestObserverInner<T>>,
+}
+
+impl<T: ForestNodeContent> ForestMutationObserver<T> {
+    pub fn take_records(&self) -> Vec<MutationRecord<T>> {
+        std::mem::take(&mut *self.inner.records.borrow_mut())
+    }
+}
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    pub fn observe(&mut self, subtree: bool) -> ForestMutationObserver<T> {
+        let inner = Rc::new(ForestObserverInner {
+            target: self.self_weak.clone().unwrap(),
+            subtree,
+            records: RefCell::new(vec![]),
+        });
+        self.context.add_observer(Rc::downgrade(&inner));
+        ForestMutationObserver { inner }
+    }
+    pub(crate) fn record_mutation(&self, record: MutationRecord<T>) {
+        for observer in self.context.observers() {
+            if observer.observes(self) {
+                observer.records.borrow_mut().push(record.clone());
+            }
+        }
+    }
+}
+
+pub(crate) type ForestObserverWeak<T> = Weak<ForestObserverInner<T>>;
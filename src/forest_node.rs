@@ -1,25 +1,47 @@
 use std::ops::{Deref, DerefMut, BitXor, Index, IndexMut, Range};
-use std::slice::Iter;
+use std::fmt;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use super::*;
 
 pub struct ForestNode<T: ForestNodeContent> {
-    context: Rc<ForestContext>,
-    self_weak: Option<ForestNodeWeak<T>>,
-    parent: Option<ForestNodeWeak<T>>,
-    children: Vec<ForestNodeRc<T>>,
+    pub(crate) context: Rc<ForestContext<T>>,
+    pub(crate) self_weak: Option<ForestNodeWeak<T>>,
+    pub(crate) parent: Option<ForestNodeWeak<T>>,
+    pub(crate) children: ForestNodeChildren<ForestNodeRc<T>>,
+    connected: bool,
+    pub(crate) revision: u64,
+    pub(crate) dirty: bool,
+    pub(crate) subtree_dirty: bool,
+    pub(crate) update_requested: bool,
+    pub(crate) subtree_update_requested: bool,
+    pub(crate) dropping: bool,
+    pub(crate) snapshot: RefCell<Option<ForestSnapshot<T>>>,
+    pub(crate) aggregate: RefCell<Option<Box<dyn Any>>>,
     content: T,
 }
 
 impl<T: ForestNodeContent> ForestNode<T> {
-    pub(crate) unsafe fn new(context: Rc<ForestContext>, content: T) -> Self {
-        let ret = Self {
+    pub(crate) unsafe fn new(context: Rc<ForestContext<T>>, content: T) -> Self {
+        let children = ForestNodeChildren::new(context.child_storage());
+        context.node_created();
+        Self {
             context,
             self_weak: None,
             parent: None,
-            children: vec![],
+            children,
+            connected: false,
+            revision: 0,
+            dirty: true,
+            subtree_dirty: true,
+            update_requested: false,
+            subtree_update_requested: false,
+            dropping: false,
+            snapshot: RefCell::new(None),
+            aggregate: RefCell::new(None),
             content,
-        };
-        ret
+        }
     }
     pub fn create_another(&mut self, content: T) -> ForestNodeRc<T> {
         unsafe {
@@ -29,19 +51,25 @@ impl<T: ForestNodeContent> ForestNode<T> {
 
     #[inline]
     pub(crate) fn set_self_weak(&mut self, weak: ForestNodeWeak<T>) {
-        self.self_weak = Some(weak.clone());
-        let ns = ForestNodeSelf::new(weak, &self.content);
-        self.content.associate_node(ns);
+        self.context.node_associated(&weak);
+        self.self_weak = Some(weak);
+        T::node_associated(self);
+    }
+    #[inline]
+    pub(crate) fn node_self(&self) -> ForestNodeSelf<T> {
+        ForestNodeSelf::new(self.self_weak.clone().unwrap(), &self.content)
     }
     #[inline]
-    pub(crate) fn context(&self) -> &Rc<ForestContext> {
+    pub(crate) fn context(&self) -> &Rc<ForestContext<T>> {
         &self.context
     }
     #[inline]
+    #[allow(clippy::needless_lifetimes)]
     pub fn another<'a, 'b>(&'a self, another_rc: &'b ForestNodeRc<T>) -> &'a Self {
         another_rc.deref_with(self)
     }
     #[inline]
+    #[allow(clippy::needless_lifetimes)]
     pub fn another_mut<'a, 'b>(&'a mut self, another_rc: &'b ForestNodeRc<T>) -> &'a mut Self {
         another_rc.deref_mut_with(self)
     }
@@ -51,10 +79,23 @@ impl<T: ForestNodeContent> ForestNode<T> {
         self.children.len()
     }
     #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.children.len() == 0
+    }
+    #[inline]
     pub fn has_parent(&self) -> bool {
         self.parent.is_some()
     }
     #[inline]
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none() && self.context.is_root(self.self_weak.as_ref().unwrap().as_ptr())
+    }
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+    #[inline]
+    #[allow(clippy::needless_lifetimes)]
     pub fn parent<'a>(&'a self) -> Option<&'a Self> {
         match self.parent {
             None => None,
@@ -67,6 +108,7 @@ impl<T: ForestNodeContent> ForestNode<T> {
         }
     }
     #[inline]
+    #[allow(clippy::needless_lifetimes)]
     pub fn parent_mut<'a>(&'a mut self) -> Option<&'a mut Self> {
         match self.parent {
             None => None,
@@ -79,23 +121,26 @@ impl<T: ForestNodeContent> ForestNode<T> {
         }
     }
     #[inline]
+    #[allow(clippy::needless_lifetimes)]
     pub fn child<'a>(&'a self, index: usize) -> Option<&'a Self> {
         self.children.get(index).map(|x| x.deref_with(self))
     }
     #[inline]
+    #[allow(clippy::needless_lifetimes)]
     pub fn child_mut<'a>(&'a mut self, index: usize) -> Option<&'a mut Self> {
-        let option_rc = self.children.get(index).map(|x| x.clone());
+        let option_rc = self.children.get(index).cloned();
         option_rc.map(move |x| x.deref_mut_with(self))
     }
     #[inline]
     pub fn children(&self, r: Range<usize>) -> Vec<ForestNodeRc<T>> {
-        self.children[r].to_vec()
+        self.children.range_to_vec(r)
     }
     #[inline]
     pub fn clone_children(&self) -> Vec<ForestNodeRc<T>> {
-        self.children.clone()
+        self.children.to_vec()
     }
     #[inline]
+    #[allow(clippy::needless_lifetimes)]
     pub fn iter_children<'a>(&'a self) -> ForestNodeIter<'a, T> {
         ForestNodeIter {
             parent: self,
@@ -104,7 +149,7 @@ impl<T: ForestNodeContent> ForestNode<T> {
     }
     #[inline]
     pub fn for_each_child<F>(&self, mut f: F) where F: FnMut(&ForestNode<T>) {
-        let children = unsafe { &*(&self.children as *const Vec<ForestNodeRc<T>>) };
+        let children = unsafe { &*(&self.children as *const ForestNodeChildren<ForestNodeRc<T>>) };
         for child_rc in children.iter() {
             {
                 let child = self.another(child_rc);
@@ -114,7 +159,7 @@ impl<T: ForestNodeContent> ForestNode<T> {
     }
     #[inline]
     pub fn for_each_child_mut<F>(&mut self, mut f: F) where F: FnMut(&mut ForestNode<T>) {
-        let children = unsafe { &*(&self.children as *const Vec<ForestNodeRc<T>>) };
+        let children = unsafe { &*(&self.children as *const ForestNodeChildren<ForestNodeRc<T>>) };
         for child_rc in children.iter() {
             {
                 let child = self.another_mut(child_rc);
@@ -128,94 +173,312 @@ impl<T: ForestNodeContent> ForestNode<T> {
         self.self_weak.as_ref().unwrap().upgrade().unwrap()
     }
     #[inline]
-    fn replace_from_old_parent(&mut self, new_parent: ForestNodeWeak<T>) {
-        let old_parent = self.parent.replace(new_parent);
+    fn is_parent_of(&self, child: &ForestNodeRc<T>) -> bool {
+        match child.deref_with(self).parent {
+            None => false,
+            Some(ref p) => p.as_ptr() == self.self_weak.as_ref().unwrap().as_ptr(),
+        }
+    }
+    fn detach_from_parent(&mut self) -> Option<ForestNodeWeak<T>> {
+        let old_parent = self.parent.take();
         match old_parent {
-            None => { },
-            Some(x) => {
-                match x.upgrade() {
-                    None => { },
-                    Some(parent) => {
-                        let self_rc = self.rc();
-                        let parent = parent.deref_mut_with(self);
-                        let i = parent.find_child_position(&self_rc).unwrap();
-                        parent.children.remove(i);
+            None => {
+                if let Some(rc) = self.context.remove_root(self.self_weak.as_ref().unwrap().as_ptr()) {
+                    if self.context.is_recording() {
+                        self.context.record_op(ForestUndoOp::RootRemoved(rc));
+                    }
+                }
+            },
+            Some(ref x) => {
+                if let Some(parent_rc) = x.upgrade() {
+                    let self_rc = self.rc();
+                    let parent = parent_rc.deref_mut_with(self);
+                    let i = parent.find_child_position(&self_rc).unwrap();
+                    let previous_sibling = if i > 0 { parent.children.get(i - 1).cloned() } else { None };
+                    let next_sibling = parent.children.get(i + 1).cloned();
+                    parent.children.remove(i);
+                    parent.invalidate_caches();
+                    parent.mark_dirty();
+                    if parent.context.is_recording() {
+                        parent.context.record_op(ForestUndoOp::Splice { parent: parent_rc.clone(), position: i, removed: vec![self_rc.clone()], inserted: vec![] });
+                    }
+                    if parent.context.has_observers() {
+                        parent.record_mutation(MutationRecord {
+                            kind: MutationRecordKind::ChildList,
+                            target: parent_rc.clone(),
+                            added_nodes: vec![],
+                            removed_nodes: vec![self_rc],
+                            previous_sibling,
+                            next_sibling,
+                        });
                     }
+                    self.notify(ForestNotification::ChildrenSpliced(parent_rc, i..(i + 1), 0));
                 }
             }
         }
+        old_parent
+    }
+    pub(crate) fn splice_children(&mut self, mut position: usize, mut removes: usize, mut inserts: Vec<ForestNodeRc<T>>) -> Vec<ForestNodeRc<T>> {
+        let replaced = self.children.range_to_vec(position..(position + removes));
+        let mut seen = HashSet::new();
+        inserts.reverse();
+        inserts.retain(|x| seen.insert(x.as_ptr()));
+        inserts.reverse();
+        let range = position..(position + removes);
+        for child in inserts.iter() {
+            if !self.is_parent_of(child) { continue }
+            let i = self.find_child_position(child).unwrap();
+            if i < range.start {
+                position -= 1;
+            } else if i < range.end {
+                removes -= 1;
+            }
+        }
+        let _group = ForestUndoGroup::begin(self.context.undo());
+        let self_weak = self.self_weak.clone().unwrap();
+        let old_parents: Vec<_> = inserts.iter().map(|child| {
+            child.deref_mut_with(self).detach_from_parent()
+        }).collect();
+        let previous_sibling = if position > 0 { self.children.get(position - 1).cloned() } else { None };
+        let next_sibling = self.children.get(position + removes).cloned();
+        let removed = self.children.splice(position, removes, inserts.clone());
+        self.invalidate_caches();
+        self.mark_dirty();
+        if self.context.is_recording() {
+            self.context.record_op(ForestUndoOp::Splice { parent: self.rc(), position, removed: removed.clone(), inserted: inserts.clone() });
+        }
+        if self.context.has_observers() {
+            self.record_mutation(MutationRecord {
+                kind: MutationRecordKind::ChildList,
+                target: self.rc(),
+                added_nodes: inserts.clone(),
+                removed_nodes: removed.clone(),
+                previous_sibling,
+                next_sibling,
+            });
+        }
+        for child in removed.iter() {
+            let c = child.deref_mut_with(self);
+            c.parent = None;
+            c.invalidate_inherited();
+        }
+        for child in inserts.iter() {
+            let c = child.deref_mut_with(self);
+            c.parent = Some(self_weak.clone());
+            c.invalidate_inherited();
+        }
+        self.notify(ForestNotification::ChildrenSpliced(self.rc(), position..(position + removes), inserts.len()));
+        for child in removed.iter() {
+            self.notify(ForestNotification::ParentChanged(child.clone(), Some(self_weak.clone()), None));
+        }
+        for (child, old_parent) in inserts.iter().zip(old_parents) {
+            self.notify(ForestNotification::ParentChanged(child.clone(), old_parent, Some(self_weak.clone())));
+        }
+        for child in removed.iter() {
+            child.deref_mut_with(self).update_connected(false);
+        }
+        let connected = self.connected;
+        for child in inserts.iter() {
+            child.deref_mut_with(self).update_connected(connected);
+        }
+        replaced
+    }
+    pub fn set_content(&mut self, content: T) {
+        let old = std::mem::replace(&mut **self, content);
+        T::node_associated(self);
+        if self.context.is_recording() {
+            let _group = ForestUndoGroup::begin(self.context.undo());
+            self.context.record_op(ForestUndoOp::Content { node: self.rc(), content: old });
+        }
+        self.mark_content_changed();
+    }
+    pub fn mark_content_changed(&mut self) {
+        self.mark_dirty();
+        self.invalidate_caches();
+        self.invalidate_inherited();
+        if self.is_connected() {
+            self.update_indexes(true);
+        }
+        if self.context.has_observers() {
+            self.record_mutation(MutationRecord {
+                kind: MutationRecordKind::Content,
+                target: self.rc(),
+                added_nodes: vec![],
+                removed_nodes: vec![],
+                previous_sibling: None,
+                next_sibling: None,
+            });
+        }
     }
+    pub(crate) fn set_root(&mut self, is_root: bool) -> bool {
+        let _group = ForestUndoGroup::begin(self.context.undo());
+        let self_rc = self.rc();
+        let changed = if is_root {
+            self.context.add_root(self_rc.clone())
+        } else {
+            self.context.remove_root(self_rc.as_ptr()).is_some()
+        };
+        if changed {
+            if self.context.is_recording() {
+                self.context.record_op(if is_root { ForestUndoOp::RootAdded(self_rc) } else { ForestUndoOp::RootRemoved(self_rc) });
+            }
+            self.update_connected(is_root);
+        }
+        changed
+    }
+    fn take_caches(&self) -> bool {
+        let snapshot = self.snapshot.borrow_mut().take();
+        let aggregate = self.aggregate.borrow_mut().take();
+        snapshot.is_some() || aggregate.is_some()
+    }
+    pub(crate) fn invalidate_caches(&mut self) {
+        if !self.take_caches() { return }
+        let mut cur = self.parent.as_ref().and_then(|x| x.upgrade());
+        while let Some(rc) = cur {
+            let n = rc.deref_with(self);
+            if !n.take_caches() { break }
+            cur = n.parent.as_ref().and_then(|x| x.upgrade());
+        }
+    }
+    pub(crate) fn update_connected(&mut self, connected: bool) {
+        if self.connected == connected { return }
+        let mut stack = vec![self.rc()];
+        while let Some(rc) = stack.pop() {
+            let n = rc.deref_mut_with(self);
+            n.connected = connected;
+            n.update_indexes(connected);
+            let len = stack.len();
+            stack.extend(n.children.iter().cloned());
+            stack[len..].reverse();
+            self.notify(if connected { ForestNotification::Connected(rc) } else { ForestNotification::Disconnected(rc) });
+        }
+    }
+    /// Linear in the number of children with either storage, positions are not indexed.
     pub fn find_child_position(&self, child: &ForestNodeRc<T>) -> Option<usize> {
         self.children.iter().position(|c| {
-            ForestNodeRc::ptr_eq(child, &c)
+            ForestNodeRc::ptr_eq(child, c)
         })
     }
+    fn check_children(&self, inserts: &[ForestNodeRc<T>]) -> Result<(), ForestChildRejection> {
+        for (index, child) in inserts.iter().enumerate() {
+            let c = child.deref_with(self);
+            if !self.content.can_accept_child(&c.content) {
+                return Err(ForestChildRejection::RejectedByParent { index });
+            }
+            if !c.content.can_be_child_of(&self.content) {
+                return Err(ForestChildRejection::RejectedByChild { index });
+            }
+        }
+        Ok(())
+    }
     pub fn append(&mut self, child: ForestNodeRc<T>) {
-        let self_rc = self.rc();
-        self.children.push(child.clone());
-        let c = child.deref_mut_with(self);
-        c.replace_from_old_parent(self_rc.downgrade());
-        c.content.parent_node_changed();
+        if let Err(e) = self.try_append(child) { panic!("{}", e) }
+    }
+    pub fn try_append(&mut self, child: ForestNodeRc<T>) -> Result<(), ForestChildRejection> {
+        self.check_children(std::slice::from_ref(&child))?;
+        self.splice_children(self.children.len(), 0, vec![child]);
+        Ok(())
     }
     pub fn insert(&mut self, child: ForestNodeRc<T>, position: usize) {
-        let self_rc = self.rc();
-        self.children.insert(position, child.clone());
-        let c = child.deref_mut_with(self);
-        c.replace_from_old_parent(self_rc.downgrade());
-        c.content.parent_node_changed();
+        if let Err(e) = self.try_insert(child, position) { panic!("{}", e) }
+    }
+    pub fn try_insert(&mut self, child: ForestNodeRc<T>, position: usize) -> Result<(), ForestChildRejection> {
+        self.check_children(std::slice::from_ref(&child))?;
+        self.splice_children(position, 0, vec![child]);
+        Ok(())
     }
     pub fn remove(&mut self, position: usize) -> ForestNodeRc<T> {
-        let child = self.children.remove(position);
-        let c = child.deref_mut_with(self);
-        c.parent.take();
-        c.content.parent_node_changed();
-        child
+        self.splice_children(position, 1, vec![]).pop().unwrap()
     }
     pub fn replace(&mut self, new_child: ForestNodeRc<T>, position: usize) -> ForestNodeRc<T> {
-        let self_rc = self.rc();
-        let old_child = self.children[position].clone();
-        self.children[position] = new_child.clone();
-        let c = old_child.deref_mut_with(self);
-        c.parent.take();
-        c.content.parent_node_changed();
-        let c = new_child.deref_mut_with(self);
-        c.replace_from_old_parent(self_rc.downgrade());
-        c.content.parent_node_changed();
-        old_child
+        match self.try_replace(new_child, position) {
+            Ok(x) => x,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    pub fn try_replace(&mut self, new_child: ForestNodeRc<T>, position: usize) -> Result<ForestNodeRc<T>, ForestChildRejection> {
+        self.check_children(std::slice::from_ref(&new_child))?;
+        Ok(self.splice_children(position, 1, vec![new_child]).pop().unwrap())
     }
     pub fn splice(&mut self, position: usize, removes: usize, inserts: Vec<ForestNodeRc<T>>) -> Box<[ForestNodeRc<T>]> {
-        let self_rc = self.rc();
-        let removes: Box<[ForestNodeRc<T>]> = self.children.splice(position..(position + removes), inserts.clone()).collect();
-        for child in removes.iter() {
-            let c = child.deref_mut_with(self);
-            c.parent.take();
-            c.content.parent_node_changed();
-        }
-        for child in inserts.iter() {
-            let c = child.deref_mut_with(self);
-            c.replace_from_old_parent(self_rc.downgrade());
-            c.content.parent_node_changed();
+        match self.try_splice(position, removes, inserts) {
+            Ok(x) => x,
+            Err(e) => panic!("{}", e),
         }
-        removes
+    }
+    pub fn try_splice(&mut self, position: usize, removes: usize, inserts: Vec<ForestNodeRc<T>>) -> Result<Box<[ForestNodeRc<T>]>, ForestChildRejection> {
+        self.check_children(&inserts)?;
+        Ok(self.splice_children(position, removes, inserts).into_boxed_slice())
     }
 
-    pub fn iter(&self) -> Iter<ForestNodeRc<T>> {
+    /// Breaking change: this returned `std::slice::Iter` before chunked storage was added.
+    /// Use `as_slice` on the returned iterator where a slice is still needed.
+    pub fn iter(&self) -> ForestNodeChildrenIter<'_, ForestNodeRc<T>> {
         self.children.iter()
     }
 }
 
 impl<T: ForestNodeContent + Clone> ForestNode<T> {
+    pub fn edit_content<F: FnOnce(&mut T)>(&mut self, f: F) {
+        let mut content = (**self).clone();
+        f(&mut content);
+        self.set_content(content);
+    }
     pub(crate) fn clone_node(&self) -> Self {
+        self.context.node_created();
         Self {
             context: self.context.clone(),
             self_weak: None,
             parent: None,
-            children: vec![],
+            children: ForestNodeChildren::new(self.context.child_storage()),
+            connected: false,
+            revision: 0,
+            dirty: true,
+            subtree_dirty: true,
+            update_requested: false,
+            subtree_update_requested: false,
+            dropping: false,
+            snapshot: RefCell::new(None),
+            aggregate: RefCell::new(None),
             content: self.content.clone(),
         }
     }
 }
 
+impl<T: ForestNodeContent> ForestNode<T> {
+    pub(crate) fn prepare_drop(&mut self) {
+        self.dropping = true;
+        T::before_drop(self);
+        if self.self_weak.as_ref().unwrap().strong_count() > 1 {
+            self.dropping = false;
+            return
+        }
+        let children = std::mem::replace(&mut self.children, ForestNodeChildren::new(self.context.child_storage()));
+        let shared: Vec<bool> = children.iter().map(|x| x.strong_count() > 1).collect();
+        let children = children.to_vec();
+        let self_weak = self.self_weak.clone();
+        let mut kept = vec![];
+        for (child, shared) in children.into_iter().zip(shared) {
+            if !shared {
+                kept.push(child);
+                continue
+            }
+            let c = child.deref_mut_with(self);
+            c.parent = None;
+            c.invalidate_inherited();
+            c.update_connected(false);
+            self.notify(ForestNotification::ParentChanged(child, self_weak.clone(), None));
+        }
+        self.children.splice(0, 0, kept);
+    }
+}
+
+impl<T: ForestNodeContent> Drop for ForestNode<T> {
+    fn drop(&mut self) {
+        self.context.node_dropped(self.self_weak.as_ref().map(|x| x.as_ptr()));
+    }
+}
+
 impl<T: ForestNodeContent> Deref for ForestNode<T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
@@ -225,6 +488,9 @@ impl<T: ForestNodeContent> Deref for ForestNode<T> {
 
 impl<T: ForestNodeContent> DerefMut for ForestNode<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        // every derived cache may depend on the content
+        self.invalidate_caches();
+        self.invalidate_inherited();
         &mut self.content
     }
 }
@@ -264,6 +530,23 @@ impl<T: ForestNodeContent> IndexMut<usize> for ForestNode<T> {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForestChildRejection {
+    RejectedByParent { index: usize },
+    RejectedByChild { index: usize },
+}
+
+impl fmt::Display for ForestChildRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForestChildRejection::RejectedByParent { index } => write!(f, "the parent node cannot accept the child node {}", index),
+            ForestChildRejection::RejectedByChild { index } => write!(f, "the child node {} cannot be a child of the parent node", index),
+        }
+    }
+}
+
+impl std::error::Error for ForestChildRejection { }
+
 pub struct ForestNodeIter<'a, T: ForestNodeContent> {
     parent: &'a ForestNode<T>,
     cur: usize,
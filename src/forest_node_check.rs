@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+use std::fmt;
+use super::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ForestInvariantError {
+    SelfWeakMismatch { path: Vec<usize> },
+    ParentMismatch { path: Vec<usize> },
+    ContextMismatch { path: Vec<usize> },
+    DuplicateChild { path: Vec<usize> },
+    Cycle { path: Vec<usize> },
+}
+
+impl fmt::Display for ForestInvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForestInvariantError::SelfWeakMismatch { path } => write!(f, "self weak of node {:?} does not point to itself", path),
+            ForestInvariantError::ParentMismatch { path } => write!(f, "parent of node {:?} does not point to its parent", path),
+            ForestInvariantError::ContextMismatch { path } => write!(f, "node {:?} is in another forest", path),
+            ForestInvariantError::DuplicateChild { path } => write!(f, "node {:?} appears more than once in its parent", path),
+            ForestInvariantError::Cycle { path } => write!(f, "node {:?} is its own ancestor or has multiple parents", path),
+        }
+    }
+}
+
+impl std::error::Error for ForestInvariantError { }
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    pub fn check_invariants(&self) -> Result<(), ForestInvariantError> {
+        let mut visited = HashSet::new();
+        let mut stack: Vec<(&ForestNode<T>, Vec<usize>)> = vec![(self, vec![])];
+        while let Some((node, path)) = stack.pop() {
+            let self_ptr = node.self_weak.as_ref().and_then(|x| x.upgrade()).map(|x| x.as_ptr());
+            match self_ptr {
+                Some(ptr) if std::ptr::eq(unsafe { (*ptr).get() }, node) => { },
+                _ => return Err(ForestInvariantError::SelfWeakMismatch { path }),
+            }
+            if !visited.insert(self_ptr.unwrap()) {
+                return Err(ForestInvariantError::Cycle { path });
+            }
+            let mut children = HashSet::new();
+            for (i, child_rc) in node.children.iter().enumerate() {
+                let mut child_path = path.clone();
+                child_path.push(i);
+                if !children.insert(child_rc.as_ptr()) {
+                    return Err(ForestInvariantError::DuplicateChild { path: child_path });
+                }
+                let child = unsafe { child_rc.forest_node() };
+                if !Rc::ptr_eq(&node.context, &child.context) {
+                    return Err(ForestInvariantError::ContextMismatch { path: child_path });
+                }
+                match child.parent.as_ref().and_then(|x| x.upgrade()) {
+                    Some(p) if p.as_ptr() == self_ptr.unwrap() => { },
+                    _ => return Err(ForestInvariantError::ParentMismatch { path: child_path }),
+                }
+                stack.push((child, child_path));
+            }
+        }
+        Ok(())
+    }
+}
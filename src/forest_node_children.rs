@@ -0,0 +1,281 @@
+use std::ops::Range;
+use std::slice;
+
+#[cfg(not(test))]
+const CHUNK_MAX: usize = 64;
+// small chunks make the unit tests reach the split and merge paths
+#[cfg(test)]
+const CHUNK_MAX: usize = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForestChildStorage {
+    #[default]
+    Vec,
+    Chunked,
+}
+
+pub(crate) enum ForestNodeChildren<E> {
+    Vec(Vec<E>),
+    Chunked(ChunkedVec<E>),
+}
+
+impl<E: Clone> ForestNodeChildren<E> {
+    pub(crate) fn new(storage: ForestChildStorage) -> Self {
+        match storage {
+            ForestChildStorage::Vec => ForestNodeChildren::Vec(vec![]),
+            ForestChildStorage::Chunked => ForestNodeChildren::Chunked(ChunkedVec::new()),
+        }
+    }
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            ForestNodeChildren::Vec(v) => v.len(),
+            ForestNodeChildren::Chunked(c) => c.len(),
+        }
+    }
+    #[inline]
+    pub(crate) fn get(&self, index: usize) -> Option<&E> {
+        match self {
+            ForestNodeChildren::Vec(v) => v.get(index),
+            ForestNodeChildren::Chunked(c) => c.get(index),
+        }
+    }
+    #[inline]
+    pub(crate) fn iter(&self) -> ForestNodeChildrenIter<'_, E> {
+        match self {
+            ForestNodeChildren::Vec(v) => ForestNodeChildrenIter { inner: ChildrenIterInner::Vec(v.iter()) },
+            ForestNodeChildren::Chunked(c) => ForestNodeChildrenIter { inner: ChildrenIterInner::Chunked(c.iter()) },
+        }
+    }
+    pub(crate) fn to_vec(&self) -> Vec<E> {
+        self.iter().cloned().collect()
+    }
+    pub(crate) fn range_to_vec(&self, r: Range<usize>) -> Vec<E> {
+        match self {
+            ForestNodeChildren::Vec(v) => v[r].to_vec(),
+            ForestNodeChildren::Chunked(c) => {
+                if r.end > c.len() { panic!("range end index {} out of range for children of length {}", r.end, c.len()) }
+                r.map(|i| c.get(i).unwrap().clone()).collect()
+            },
+        }
+    }
+    pub(crate) fn remove(&mut self, index: usize) -> E {
+        match self {
+            ForestNodeChildren::Vec(v) => v.remove(index),
+            ForestNodeChildren::Chunked(c) => c.remove(index),
+        }
+    }
+    pub(crate) fn splice(&mut self, position: usize, removes: usize, inserts: Vec<E>) -> Vec<E> {
+        match self {
+            ForestNodeChildren::Vec(v) => v.splice(position..(position + removes), inserts).collect(),
+            ForestNodeChildren::Chunked(c) => {
+                if position + removes > c.len() { panic!("range end index {} out of range for children of length {}", position + removes, c.len()) }
+                let ret = (0..removes).map(|_| c.remove(position)).collect();
+                for (i, item) in inserts.into_iter().enumerate() {
+                    c.insert(position + i, item);
+                }
+                ret
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ForestNodeChildrenIter<'a, E> {
+    inner: ChildrenIterInner<'a, E>,
+}
+
+impl<'a, E> ForestNodeChildrenIter<'a, E> {
+    /// The remaining children as a slice, available with `ForestChildStorage::Vec` only.
+    pub fn as_slice(&self) -> Option<&'a [E]> {
+        match &self.inner {
+            ChildrenIterInner::Vec(x) => Some(x.as_slice()),
+            ChildrenIterInner::Chunked(_) => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum ChildrenIterInner<'a, E> {
+    Vec(slice::Iter<'a, E>),
+    Chunked(ChunkedIter<'a, E>),
+}
+
+impl<'a, E> Iterator for ForestNodeChildrenIter<'a, E> {
+    type Item = &'a E;
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            ChildrenIterInner::Vec(x) => x.next(),
+            ChildrenIterInner::Chunked(x) => x.next(),
+        }
+    }
+}
+
+enum ChunkNode<E> {
+    Leaf(Vec<E>),
+    Branch(Vec<(usize, Box<ChunkNode<E>>)>),
+}
+
+impl<E> ChunkNode<E> {
+    fn width(&self) -> usize {
+        match self {
+            ChunkNode::Leaf(v) => v.len(),
+            ChunkNode::Branch(v) => v.len(),
+        }
+    }
+    fn locate(branch: &[(usize, Box<ChunkNode<E>>)], mut index: usize, for_insert: bool) -> (usize, usize) {
+        let last = branch.len() - 1;
+        for (i, (len, _)) in branch.iter().enumerate() {
+            if index < *len || (for_insert && (index == *len || i == last)) {
+                return (i, index);
+            }
+            index -= len;
+        }
+        panic!("index out of range for chunked children")
+    }
+    fn get(&self, index: usize) -> &E {
+        match self {
+            ChunkNode::Leaf(v) => &v[index],
+            ChunkNode::Branch(v) => {
+                let (i, index) = Self::locate(v, index, false);
+                v[i].1.get(index)
+            },
+        }
+    }
+    fn insert(&mut self, index: usize, item: E) -> Option<(usize, Box<ChunkNode<E>>)> {
+        match self {
+            ChunkNode::Leaf(v) => {
+                v.insert(index, item);
+                if v.len() <= CHUNK_MAX { return None }
+                let right = v.split_off(v.len() / 2);
+                Some((right.len(), Box::new(ChunkNode::Leaf(right))))
+            },
+            ChunkNode::Branch(v) => {
+                let (i, index) = Self::locate(v, index, true);
+                v[i].0 += 1;
+                if let Some((len, node)) = v[i].1.insert(index, item) {
+                    v[i].0 -= len;
+                    v.insert(i + 1, (len, node));
+                }
+                if v.len() <= CHUNK_MAX { return None }
+                let right = v.split_off(v.len() / 2);
+                Some((right.iter().map(|x| x.0).sum(), Box::new(ChunkNode::Branch(right))))
+            },
+        }
+    }
+    fn remove(&mut self, index: usize) -> E {
+        match self {
+            ChunkNode::Leaf(v) => v.remove(index),
+            ChunkNode::Branch(v) => {
+                let (i, index) = Self::locate(v, index, false);
+                let ret = v[i].1.remove(index);
+                v[i].0 -= 1;
+                if v[i].0 == 0 {
+                    v.remove(i);
+                } else {
+                    Self::merge_small(v, i);
+                }
+                ret
+            },
+        }
+    }
+    fn merge_small(v: &mut Vec<(usize, Box<ChunkNode<E>>)>, i: usize) {
+        if v[i].1.width() >= CHUNK_MAX / 4 { return }
+        let (a, b) = if i + 1 < v.len() {
+            (i, i + 1)
+        } else if i > 0 {
+            (i - 1, i)
+        } else {
+            return
+        };
+        if v[a].1.width() + v[b].1.width() > CHUNK_MAX { return }
+        let (len, node) = v.remove(b);
+        v[a].0 += len;
+        match (&mut *v[a].1, *node) {
+            (ChunkNode::Leaf(x), ChunkNode::Leaf(y)) => x.extend(y),
+            (ChunkNode::Branch(x), ChunkNode::Branch(y)) => x.extend(y),
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub(crate) struct ChunkedVec<E> {
+    root: ChunkNode<E>,
+    len: usize,
+}
+
+impl<E> ChunkedVec<E> {
+    pub(crate) fn new() -> Self {
+        Self {
+            root: ChunkNode::Leaf(vec![]),
+            len: 0,
+        }
+    }
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+    pub(crate) fn get(&self, index: usize) -> Option<&E> {
+        if index >= self.len { return None }
+        Some(self.root.get(index))
+    }
+    pub(crate) fn insert(&mut self, index: usize, item: E) {
+        if index > self.len { panic!("insertion index {} out of range for children of length {}", index, self.len) }
+        self.len += 1;
+        if let Some(right) = self.root.insert(index, item) {
+            let left = std::mem::replace(&mut self.root, ChunkNode::Branch(vec![]));
+            self.root = ChunkNode::Branch(vec![(self.len - right.0, Box::new(left)), right]);
+        }
+    }
+    pub(crate) fn remove(&mut self, index: usize) -> E {
+        if index >= self.len { panic!("removal index {} out of range for children of length {}", index, self.len) }
+        self.len -= 1;
+        let ret = self.root.remove(index);
+        loop {
+            let next = match &mut self.root {
+                ChunkNode::Branch(v) if v.len() <= 1 => {
+                    match v.pop() {
+                        None => ChunkNode::Leaf(vec![]),
+                        Some((_, node)) => *node,
+                    }
+                },
+                _ => break,
+            };
+            self.root = next;
+        }
+        ret
+    }
+    pub(crate) fn iter(&self) -> ChunkedIter<'_, E> {
+        match &self.root {
+            ChunkNode::Leaf(v) => ChunkedIter { stack: vec![], leaf: v.iter() },
+            ChunkNode::Branch(v) => ChunkedIter { stack: vec![v.iter()], leaf: [].iter() },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ChunkedIter<'a, E> {
+    stack: Vec<slice::Iter<'a, (usize, Box<ChunkNode<E>>)>>,
+    leaf: slice::Iter<'a, E>,
+}
+
+impl<'a, E> Iterator for ChunkedIter<'a, E> {
+    type Item = &'a E;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(x) = self.leaf.next() {
+                return Some(x);
+            }
+            let next = self.stack.last_mut()?.next();
+            match next {
+                None => { self.stack.pop(); },
+                Some((_, node)) => {
+                    match &**node {
+                        ChunkNode::Leaf(v) => self.leaf = v.iter(),
+                        ChunkNode::Branch(v) => self.stack.push(v.iter()),
+                    }
+                },
+            }
+        }
+    }
+}
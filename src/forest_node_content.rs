@@ -1,8 +1,49 @@
+use std::ops::Range;
 use super::*;
 
 pub trait ForestNodeContent {
     #[inline]
     fn associate_node(&mut self, _node: ForestNodeSelf<Self>) where Self: Sized { }
     #[inline]
+    fn node_associated(node: &mut ForestNode<Self>) where Self: Sized {
+        let ns = node.node_self();
+        node.associate_node(ns);
+    }
+    #[inline]
     fn parent_node_changed(&mut self) where Self: Sized { }
+    #[inline]
+    fn parent_node_changed_with(&mut self, _old_parent: Option<ForestNodeWeak<Self>>, _new_parent: Option<ForestNodeWeak<Self>>) where Self: Sized {
+        self.parent_node_changed()
+    }
+    #[inline]
+    fn node_parent_changed(node: &mut ForestNode<Self>, old_parent: Option<ForestNodeWeak<Self>>, new_parent: Option<ForestNodeWeak<Self>>) where Self: Sized {
+        node.parent_node_changed_with(old_parent, new_parent)
+    }
+    #[inline]
+    fn can_accept_child(&self, _child: &Self) -> bool where Self: Sized { true }
+    #[inline]
+    fn can_be_child_of(&self, _parent: &Self) -> bool where Self: Sized { true }
+    #[inline]
+    fn connected(&mut self) where Self: Sized { }
+    #[inline]
+    fn disconnected(&mut self) where Self: Sized { }
+    #[inline]
+    fn child_inserted(&mut self, _index: usize) where Self: Sized { }
+    #[inline]
+    fn child_removed(&mut self, _index: usize) where Self: Sized { }
+    #[inline]
+    fn children_spliced(&mut self, range: Range<usize>, inserted: usize) where Self: Sized {
+        for index in range.clone().rev() {
+            self.child_removed(index);
+        }
+        for index in range.start..(range.start + inserted) {
+            self.child_inserted(index);
+        }
+    }
+    #[inline]
+    fn update(_node: &mut ForestNode<Self>) where Self: Sized { }
+    #[inline]
+    fn before_drop(_node: &mut ForestNode<Self>) where Self: Sized { }
+    #[inline]
+    fn trace_node_rcs(&self, _f: &mut dyn FnMut(&ForestNodeRc<Self>)) where Self: Sized { }
 }
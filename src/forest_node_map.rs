@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use super::*;
+
+pub struct ForestNodeMap<T: ForestNodeContent, V> {
+    entries: HashMap<ForestNodePtr<T>, (ForestNodeWeak<T>, V)>,
+    cleanup_size: usize,
+}
+
+impl<T: ForestNodeContent, V> ForestNodeMap<T, V> {
+    pub fn new() -> Self {
+        Self { entries: HashMap::new(), cleanup_size: 64 }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn insert(&mut self, node: &ForestNodeRc<T>, value: V) -> Option<V> {
+        let ret = self.entries.insert(node.as_ptr(), (node.downgrade(), value)).map(|x| x.1);
+        if self.entries.len() > self.cleanup_size {
+            self.remove_stale();
+            self.cleanup_size = self.entries.len() * 2 + 64;
+        }
+        ret
+    }
+    pub fn get(&self, node: &ForestNodeRc<T>) -> Option<&V> {
+        self.entries.get(&node.as_ptr()).map(|x| &x.1)
+    }
+    pub fn get_mut(&mut self, node: &ForestNodeRc<T>) -> Option<&mut V> {
+        self.entries.get_mut(&node.as_ptr()).map(|x| &mut x.1)
+    }
+    pub fn contains_key(&self, node: &ForestNodeRc<T>) -> bool {
+        self.entries.contains_key(&node.as_ptr())
+    }
+    pub fn remove(&mut self, node: &ForestNodeRc<T>) -> Option<V> {
+        self.entries.remove(&node.as_ptr()).map(|x| x.1)
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+    pub fn remove_stale(&mut self) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|_, (weak, _)| weak.upgrade().is_some());
+        len - self.entries.len()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (ForestNodeRc<T>, &V)> {
+        self.entries.values().filter_map(|(weak, v)| weak.upgrade().map(|rc| (rc, v)))
+    }
+}
+
+impl<T: ForestNodeContent, V> Default for ForestNodeMap<T, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
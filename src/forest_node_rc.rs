@@ -1,3 +1,7 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
 use std::cell::UnsafeCell;
 use std::rc::{Rc, Weak};
@@ -11,7 +15,7 @@ pub struct ForestNodeRc<T: ForestNodeContent> {
 
 impl<T: ForestNodeContent> ForestNodeRc<T> {
     #[inline]
-    pub(crate) unsafe fn create_in_context(context: Rc<ForestContext>, content: T) -> Self {
+    pub(crate) unsafe fn create_in_context(context: Rc<ForestContext<T>>, content: T) -> Self {
         let forest_node = ForestNode::new(context, content);
         let ret = Self {
             forest_node: Rc::new(UnsafeCell::new(forest_node)),
@@ -28,16 +32,26 @@ impl<T: ForestNodeContent> ForestNodeRc<T> {
     }
 
     #[inline]
+    #[allow(clippy::missing_safety_doc)]
     pub unsafe fn forest_node<'a>(&self) -> &'a ForestNode<T> {
         &*self.forest_node.get()
     }
     #[inline]
+    #[allow(clippy::missing_safety_doc)]
     pub unsafe fn forest_node_mut<'a>(&self) -> &'a mut ForestNode<T> {
         &mut *self.forest_node.get()
     }
     pub fn ptr_eq(a: &Self, b: &Self) -> bool {
         Rc::ptr_eq(&a.forest_node, &b.forest_node)
     }
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> ForestNodePtr<T> {
+        Rc::as_ptr(&self.forest_node)
+    }
+    #[inline]
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.forest_node)
+    }
     pub fn downgrade(&self) -> ForestNodeWeak<T> {
         ForestNodeWeak {
             forest_node: Rc::downgrade(&self.forest_node),
@@ -45,15 +59,17 @@ impl<T: ForestNodeContent> ForestNodeRc<T> {
     }
     #[inline]
     pub fn into_ptr(self) -> ForestNodePtr<T> {
-        Rc::into_raw(self.forest_node)
+        let this = ManuallyDrop::new(self);
+        Rc::into_raw(unsafe { std::ptr::read(&this.forest_node) })
     }
     #[inline]
+    #[allow(clippy::missing_safety_doc)]
     pub unsafe fn from_ptr(ptr: ForestNodePtr<T>, need_clone: bool) -> Self {
         let ret = Self {
             forest_node: Rc::from_raw(ptr),
         };
         if need_clone {
-            Rc::into_raw(ret.forest_node.clone());
+            let _ = Rc::into_raw(ret.forest_node.clone());
         }
         ret
     }
@@ -64,6 +80,7 @@ impl<T: ForestNodeContent> ForestNodeRc<T> {
             forest_node: unsafe { self.forest_node() },
         }
     }
+    #[allow(clippy::result_unit_err)]
     pub fn try_borrow<'a>(&self) -> Result<ForestNodeRef<'a, T>, ()> {
         match ForestContext::try_borrow(unsafe { self.forest_node() }.context()) {
             Err(_) => Err(()),
@@ -81,6 +98,7 @@ impl<T: ForestNodeContent> ForestNodeRc<T> {
             forest_node: unsafe { self.forest_node_mut() },
         }
     }
+    #[allow(clippy::result_unit_err)]
     pub fn try_borrow_mut<'a>(&self) -> Result<ForestNodeRefMut<'a, T>, ()> {
         match ForestContext::try_borrow_mut(unsafe { self.forest_node() }.context()) {
             Err(_) => Err(()),
@@ -92,12 +110,14 @@ impl<T: ForestNodeContent> ForestNodeRc<T> {
             }
         }
     }
+    #[allow(clippy::needless_lifetimes)]
     pub fn deref_with<'a, 'b>(&'b self, source: &'a ForestNode<T>) -> &'a ForestNode<T> {
         if !Rc::ptr_eq(source.context(), unsafe { self.forest_node() }.context()) {
             panic!("A ForestNode can only be deref by another ForestNode in the same Forest");
         }
         unsafe { self.forest_node() }
     }
+    #[allow(clippy::needless_lifetimes)]
     pub fn deref_mut_with<'a, 'b>(&'b self, source: &'a mut ForestNode<T>) -> &'a mut ForestNode<T> {
         if !Rc::ptr_eq(source.context(), unsafe { self.forest_node() }.context()) {
             panic!("A ForestNode can only be deref mut by another ForestNode in the same Forest");
@@ -126,9 +146,65 @@ impl<T: ForestNodeContent + Clone> ForestNodeRc<T> {
     }
 }
 
+impl<T: ForestNodeContent> Drop for ForestNodeRc<T> {
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.forest_node) > 1 { return }
+        let n = unsafe { self.forest_node_mut() };
+        if n.dropping { return }
+        // the hook needs exclusive access, so it waits for the current borrow to end
+        match ForestContext::try_borrow_mut(n.context()) {
+            Ok(_status) => {
+                n.prepare_drop();
+                n.flush_notifications();
+            },
+            Err(_) => n.context().push_notification(ForestNotification::Dropped(self.clone())),
+        }
+    }
+}
+
+impl<T: ForestNodeContent> PartialEq for ForestNodeRc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Self::ptr_eq(self, other)
+    }
+}
+
+impl<T: ForestNodeContent> Eq for ForestNodeRc<T> { }
+
+impl<T: ForestNodeContent> Hash for ForestNodeRc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ptr().hash(state);
+    }
+}
+
+impl<T: ForestNodeContent> PartialOrd for ForestNodeRc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ForestNodeContent> Ord for ForestNodeRc<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_ptr().cmp(&other.as_ptr())
+    }
+}
+
+impl<T: ForestNodeContent + fmt::Debug> fmt::Debug for ForestNodeRc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("ForestNodeRc");
+        d.field("ptr", &self.as_ptr());
+        let n = unsafe { self.forest_node() };
+        if n.context().is_borrowed_mut() {
+            d.field("content", &format_args!("<borrowed>"));
+        } else {
+            d.field("content", &**n);
+        }
+        d.finish()
+    }
+}
+
 
 pub struct ForestNodeRef<'a, T: ForestNodeContent> {
-    _status: ForestContextRef,
+    _status: ForestContextRef<T>,
     forest_node: &'a ForestNode<T>,
 }
 
@@ -141,7 +217,7 @@ impl<'a, T: ForestNodeContent> Deref for ForestNodeRef<'a, T> {
 
 
 pub struct ForestNodeRefMut<'a, T: ForestNodeContent> {
-    _status: ForestContextRefMut,
+    _status: ForestContextRefMut<T>,
     forest_node: &'a mut ForestNode<T>,
 }
 
@@ -158,12 +234,32 @@ impl<'a, T: ForestNodeContent> DerefMut for ForestNodeRefMut<'a, T> {
     }
 }
 
+impl<'a, T: ForestNodeContent> Drop for ForestNodeRefMut<'a, T> {
+    fn drop(&mut self) {
+        if !self.forest_node.context().has_notifications() { return }
+        // hooks must not run on a half-mutated tree while unwinding
+        if std::thread::panicking() {
+            drop(self.forest_node.context().take_notifications());
+        } else {
+            self.forest_node.flush_notifications();
+        }
+    }
+}
+
 
 pub struct ForestNodeWeak<T: ForestNodeContent> {
     forest_node: Weak<UnsafeCell<ForestNode<T>>>,
 }
 
 impl<T: ForestNodeContent> ForestNodeWeak<T> {
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> ForestNodePtr<T> {
+        self.forest_node.as_ptr()
+    }
+    #[inline]
+    pub(crate) fn strong_count(&self) -> usize {
+        self.forest_node.strong_count()
+    }
     pub fn upgrade(&self) -> Option<ForestNodeRc<T>> {
         let option_rc = self.forest_node.upgrade();
         match option_rc {
@@ -176,23 +272,7 @@ impl<T: ForestNodeContent> ForestNodeWeak<T> {
         }
     }
     pub fn ptr_eq(a: &Self, b: &Self) -> bool {
-        let a = a.forest_node.upgrade();
-        let b = b.forest_node.upgrade();
-        match a {
-            None => {
-                b.is_none()
-            },
-            Some(ref ai) => {
-                match b {
-                    None => {
-                        false
-                    },
-                    Some(ref bi) => {
-                        Rc::ptr_eq(ai, bi)
-                    }
-                }
-            }
-        }
+        Weak::ptr_eq(&a.forest_node, &b.forest_node)
     }
 }
 
@@ -203,3 +283,35 @@ impl<T: ForestNodeContent> Clone for ForestNodeWeak<T> {
         }
     }
 }
+
+impl<T: ForestNodeContent> PartialEq for ForestNodeWeak<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Self::ptr_eq(self, other)
+    }
+}
+
+impl<T: ForestNodeContent> Eq for ForestNodeWeak<T> { }
+
+impl<T: ForestNodeContent> Hash for ForestNodeWeak<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ptr().hash(state);
+    }
+}
+
+impl<T: ForestNodeContent> PartialOrd for ForestNodeWeak<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ForestNodeContent> Ord for ForestNodeWeak<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_ptr().cmp(&other.as_ptr())
+    }
+}
+
+impl<T: ForestNodeContent> fmt::Debug for ForestNodeWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForestNodeWeak").field("ptr", &self.as_ptr()).finish()
+    }
+}
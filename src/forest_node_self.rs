@@ -16,12 +16,14 @@ impl<T: ForestNodeContent> ForestNodeSelf<T> {
     pub fn rc(&self) -> ForestNodeRc<T> {
         self.weak.upgrade().unwrap()
     }
+    #[allow(clippy::needless_lifetimes, clippy::ptr_eq)]
     pub fn deref_by<'a, 'b>(&'b self, content: &'a T) -> &'a ForestNode<T> {
         if content as *const T != self.content_ptr {
             panic!("ForestNodeSelf can only be deref by corresponding ForestNodeContent");
         }
         unsafe { self.weak.upgrade().unwrap().forest_node() }
     }
+    #[allow(clippy::needless_lifetimes, clippy::ptr_eq)]
     pub fn deref_mut_by<'a, 'b>(&'b self, content: &'a mut T) -> &'a mut ForestNode<T> {
         if content as *const T != self.content_ptr {
             panic!("ForestNodeSelf can only be deref mut by corresponding ForestNodeContent");
@@ -0,0 +1,56 @@
+use std::ops::Range;
+use super::*;
+
+pub(crate) enum ForestNotification<T: ForestNodeContent> {
+    ChildrenSpliced(ForestNodeRc<T>, Range<usize>, usize),
+    ParentChanged(ForestNodeRc<T>, Option<ForestNodeWeak<T>>, Option<ForestNodeWeak<T>>),
+    Connected(ForestNodeRc<T>),
+    Disconnected(ForestNodeRc<T>),
+    Dropped(ForestNodeRc<T>),
+}
+
+impl<T: ForestNodeContent> ForestNotification<T> {
+    fn dispatch(self) {
+        match self {
+            ForestNotification::ChildrenSpliced(node, range, inserted) => {
+                unsafe { node.forest_node_mut() }.children_spliced(range, inserted);
+            },
+            ForestNotification::ParentChanged(node, old_parent, new_parent) => {
+                T::node_parent_changed(unsafe { node.forest_node_mut() }, old_parent, new_parent);
+            },
+            ForestNotification::Connected(node) => {
+                unsafe { node.forest_node_mut() }.connected();
+            },
+            ForestNotification::Disconnected(node) => {
+                unsafe { node.forest_node_mut() }.disconnected();
+            },
+            ForestNotification::Dropped(node) => {
+                if node.strong_count() == 1 {
+                    unsafe { node.forest_node_mut() }.prepare_drop();
+                }
+            },
+        }
+    }
+}
+
+impl<T: ForestNodeContent> ForestContext<T> {
+    pub(crate) fn flush_notifications(&self) {
+        while let Some(notification) = self.pop_notification() {
+            notification.dispatch();
+        }
+    }
+}
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    #[inline]
+    pub(crate) fn notify(&mut self, notification: ForestNotification<T>) {
+        if self.context.is_deferred() {
+            self.context.push_notification(notification);
+        } else {
+            notification.dispatch();
+        }
+    }
+    pub fn flush_notifications(&mut self) {
+        self.context.flush_notifications();
+    }
+}
@@ -0,0 +1,72 @@
+use std::ops::{Deref, Index};
+use super::*;
+
+struct ForestSnapshotInner<T> {
+    content: T,
+    children: Vec<ForestSnapshot<T>>,
+}
+
+pub struct ForestSnapshot<T> {
+    inner: Rc<ForestSnapshotInner<T>>,
+}
+
+impl<T> ForestSnapshot<T> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.children.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.children.is_empty()
+    }
+    #[inline]
+    pub fn child(&self, index: usize) -> Option<&Self> {
+        self.inner.children.get(index)
+    }
+    #[inline]
+    pub fn iter_children(&self) -> std::slice::Iter<'_, Self> {
+        self.inner.children.iter()
+    }
+    #[inline]
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Rc::ptr_eq(&a.inner, &b.inner)
+    }
+}
+
+impl<T> Clone for ForestSnapshot<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Index<usize> for ForestSnapshot<T> {
+    type Output = ForestSnapshot<T>;
+    fn index(&self, index: usize) -> &Self::Output {
+        self.child(index).unwrap()
+    }
+}
+
+impl<T> Deref for ForestSnapshot<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.inner.content
+    }
+}
+
+impl<T: ForestNodeContent + Clone> ForestNode<T> {
+    pub fn snapshot(&self) -> ForestSnapshot<T> {
+        if let Some(ref x) = *self.snapshot.borrow() {
+            return x.clone();
+        }
+        let ret = ForestSnapshot {
+            inner: Rc::new(ForestSnapshotInner {
+                content: (**self).clone(),
+                children: self.iter_children().map(|x| x.snapshot()).collect(),
+            }),
+        };
+        *self.snapshot.borrow_mut() = Some(ret.clone());
+        ret
+    }
+}
@@ -0,0 +1,31 @@
+use std::panic::{self, AssertUnwindSafe};
+use super::*;
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    pub fn transaction<R, E, F>(&mut self, f: F) -> Result<R, E> where F: FnOnce(&mut ForestNode<T>) -> Result<R, E> {
+        let undo = self.context.undo();
+        let group = ForestUndoGroup::begin(undo.clone());
+        let undo_mark = undo.as_ref().map(|u| u.mark());
+        self.context.begin_transaction();
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+        let journal = self.context.end_transaction();
+        match ret {
+            Ok(Ok(_)) => self.context.commit_transaction(journal),
+            _ => {
+                self.context.begin_transaction();
+                for op in journal.into_iter().rev() {
+                    op.revert(self);
+                }
+                self.context.end_transaction();
+                if let (Some(u), Some(mark)) = (&undo, undo_mark) {
+                    u.restore(mark);
+                }
+            },
+        }
+        drop(group);
+        match ret {
+            Ok(x) => x,
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+}
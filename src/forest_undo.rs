@@ -0,0 +1,182 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Weak;
+use super::*;
+
+pub(crate) enum ForestUndoOp<T: ForestNodeContent> {
+    Splice {
+        parent: ForestNodeRc<T>,
+        position: usize,
+        removed: Vec<ForestNodeRc<T>>,
+        inserted: Vec<ForestNodeRc<T>>,
+    },
+    Content {
+        node: ForestNodeRc<T>,
+        content: T,
+    },
+    RootAdded(ForestNodeRc<T>),
+    RootRemoved(ForestNodeRc<T>),
+}
+
+impl<T: ForestNodeContent> ForestUndoOp<T> {
+    pub(crate) fn clone_structural(&self) -> Option<Self> {
+        match self {
+            ForestUndoOp::Splice { parent, position, removed, inserted } => Some(ForestUndoOp::Splice {
+                parent: parent.clone(),
+                position: *position,
+                removed: removed.clone(),
+                inserted: inserted.clone(),
+            }),
+            ForestUndoOp::Content { .. } => None,
+            ForestUndoOp::RootAdded(x) => Some(ForestUndoOp::RootAdded(x.clone())),
+            ForestUndoOp::RootRemoved(x) => Some(ForestUndoOp::RootRemoved(x.clone())),
+        }
+    }
+    pub(crate) fn revert(self, node: &mut ForestNode<T>) {
+        match self {
+            ForestUndoOp::Splice { parent, position, removed, inserted } => {
+                parent.deref_mut_with(node).splice_children(position, inserted.len(), removed);
+            },
+            ForestUndoOp::Content { node: target, content } => {
+                target.deref_mut_with(node).set_content(content);
+            },
+            ForestUndoOp::RootAdded(x) => {
+                x.deref_mut_with(node).set_root(false);
+            },
+            ForestUndoOp::RootRemoved(x) => {
+                x.deref_mut_with(node).set_root(true);
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum UndoMode {
+    Normal,
+    Undoing,
+    Redoing,
+}
+
+pub(crate) struct ForestUndoInner<T: ForestNodeContent> {
+    undo_stack: RefCell<Vec<Vec<ForestUndoOp<T>>>>,
+    redo_stack: RefCell<Vec<Vec<ForestUndoOp<T>>>>,
+    current: RefCell<Vec<ForestUndoOp<T>>>,
+    depth: Cell<usize>,
+    mode: Cell<UndoMode>,
+}
+
+impl<T: ForestNodeContent> ForestUndoInner<T> {
+    pub(crate) fn begin(&self) {
+        self.depth.set(self.depth.get() + 1);
+    }
+    pub(crate) fn end(&self) {
+        self.depth.set(self.depth.get() - 1);
+        if self.depth.get() > 0 { return }
+        let mode = self.mode.replace(UndoMode::Normal);
+        let group = std::mem::take(&mut *self.current.borrow_mut());
+        if group.is_empty() { return }
+        match mode {
+            UndoMode::Normal => {
+                self.undo_stack.borrow_mut().push(group);
+                self.redo_stack.borrow_mut().clear();
+            },
+            UndoMode::Undoing => self.redo_stack.borrow_mut().push(group),
+            UndoMode::Redoing => self.undo_stack.borrow_mut().push(group),
+        }
+    }
+    pub(crate) fn record(&self, op: ForestUndoOp<T>) {
+        self.current.borrow_mut().push(op);
+    }
+    pub(crate) fn mark(&self) -> (usize, usize) {
+        (self.depth.get(), self.current.borrow().len())
+    }
+    pub(crate) fn restore(&self, mark: (usize, usize)) {
+        self.depth.set(mark.0);
+        self.current.borrow_mut().truncate(mark.1);
+    }
+}
+
+pub(crate) type ForestUndoWeak<T> = Weak<ForestUndoInner<T>>;
+
+pub(crate) struct ForestUndoGroup<T: ForestNodeContent> {
+    undo: Option<Rc<ForestUndoInner<T>>>,
+}
+
+impl<T: ForestNodeContent> ForestUndoGroup<T> {
+    pub(crate) fn begin(undo: Option<Rc<ForestUndoInner<T>>>) -> Self {
+        if let Some(ref u) = undo { u.begin() }
+        Self { undo }
+    }
+}
+
+impl<T: ForestNodeContent> Drop for ForestUndoGroup<T> {
+    fn drop(&mut self) {
+        if let Some(ref u) = self.undo { u.end() }
+    }
+}
+
+pub struct ForestUndoStack<T: ForestNodeContent> {
+    inner: Rc<ForestUndoInner<T>>,
+}
+
+impl<T: ForestNodeContent> ForestUndoStack<T> {
+    pub fn new(forest: &mut Forest<T>) -> Self {
+        ForestContext::borrow_mut(&forest.context);
+        let inner = Rc::new(ForestUndoInner {
+            undo_stack: RefCell::new(vec![]),
+            redo_stack: RefCell::new(vec![]),
+            current: RefCell::new(vec![]),
+            depth: Cell::new(0),
+            mode: Cell::new(UndoMode::Normal),
+        });
+        forest.context.set_undo(Some(Rc::downgrade(&inner)));
+        Self { inner }
+    }
+    pub fn can_undo(&self) -> bool {
+        !self.inner.undo_stack.borrow().is_empty()
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.inner.redo_stack.borrow().is_empty()
+    }
+    pub fn begin_group(&self) {
+        self.inner.begin();
+    }
+    pub fn end_group(&self) {
+        self.inner.end();
+    }
+    pub fn clear(&self) {
+        self.inner.undo_stack.borrow_mut().clear();
+        self.inner.redo_stack.borrow_mut().clear();
+    }
+    pub fn undo(&self, node: &mut ForestNode<T>) -> bool {
+        let group = self.inner.undo_stack.borrow_mut().pop();
+        match group {
+            None => false,
+            Some(group) => {
+                self.replay(node, group, UndoMode::Undoing);
+                true
+            }
+        }
+    }
+    pub fn redo(&self, node: &mut ForestNode<T>) -> bool {
+        let group = self.inner.redo_stack.borrow_mut().pop();
+        match group {
+            None => false,
+            Some(group) => {
+                self.replay(node, group, UndoMode::Redoing);
+                true
+            }
+        }
+    }
+    fn replay(&self, node: &mut ForestNode<T>, group: Vec<ForestUndoOp<T>>, mode: UndoMode) {
+        match node.context().undo() {
+            Some(ref x) if Rc::ptr_eq(x, &self.inner) => { },
+            _ => panic!("A ForestUndoStack can only be used with nodes in its Forest"),
+        }
+        if self.inner.depth.get() > 0 { panic!("Cannot undo or redo inside an undo group") }
+        self.inner.mode.set(mode);
+        let _group = ForestUndoGroup::begin(Some(self.inner.clone()));
+        for op in group.into_iter().rev() {
+            op.revert(node);
+        }
+    }
+}
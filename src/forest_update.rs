@@ -0,0 +1,75 @@
+use super::*;
+
+impl<T: ForestNodeContent> ForestNode<T> {
+    #[inline]
+    pub fn is_update_requested(&self) -> bool {
+        self.update_requested
+    }
+    pub fn request_update(&mut self) {
+        if self.update_requested { return }
+        self.update_requested = true;
+        self.context.push_update(self.self_weak.clone().unwrap());
+    }
+    fn mark_update_path(&mut self) {
+        // walk to the root, a moved subtree may carry flags under an unflagged parent
+        let mut cur = Some(self.rc());
+        while let Some(rc) = cur {
+            let n = rc.deref_mut_with(self);
+            n.subtree_update_requested = true;
+            cur = n.parent.as_ref().and_then(|x| x.upgrade());
+        }
+    }
+}
+
+impl<T: ForestNodeContent> Forest<T> {
+    fn schedule_updates(&self, scheduled: &mut Vec<ForestNodeRc<T>>) {
+        for weak in self.context.take_updates() {
+            let rc = match weak.upgrade() {
+                Some(rc) => rc,
+                None => continue,
+            };
+            let n = unsafe { rc.forest_node_mut() };
+            if !n.is_connected() {
+                n.update_requested = false;
+                continue
+            }
+            n.mark_update_path();
+            scheduled.push(rc);
+        }
+    }
+    pub fn run_updates(&mut self) -> usize {
+        let _status = ForestContext::borrow_mut(&self.context);
+        let mut count = 0;
+        loop {
+            let mut scheduled = vec![];
+            self.schedule_updates(&mut scheduled);
+            if scheduled.is_empty() { break }
+            let mut stack: Vec<_> = self.context.roots().into_iter().rev().map(|rc| (rc, None)).collect();
+            while let Some((rc, parent)) = stack.pop() {
+                let n = unsafe { rc.forest_node_mut() };
+                if !n.subtree_update_requested || !n.is_connected() { continue }
+                // a node moved by an earlier update is reached through its new parent
+                if n.parent.as_ref().map(|x| x.as_ptr()) != parent { continue }
+                n.subtree_update_requested = false;
+                if n.update_requested {
+                    n.update_requested = false;
+                    T::update(n);
+                    n.flush_notifications();
+                    count += 1;
+                    self.schedule_updates(&mut scheduled);
+                }
+                let len = stack.len();
+                let ptr = n.self_weak.as_ref().map(|x| x.as_ptr());
+                stack.extend(n.children.iter().filter(|c| c.deref_with(n).subtree_update_requested).map(|c| (c.clone(), ptr)));
+                stack[len..].reverse();
+            }
+            // requests behind the current position are left for the next pass
+            for rc in scheduled {
+                if unsafe { rc.forest_node() }.update_requested {
+                    self.context.push_update(rc.downgrade());
+                }
+            }
+        }
+        count
+    }
+}
@@ -2,18 +2,52 @@ use std::rc::Rc;
 use std::marker::PhantomData;
 
 mod forest_node;
-pub use self::forest_node::ForestNode;
+pub use self::forest_node::{ForestNode, ForestNodeIter, ForestChildRejection};
 mod forest_node_content;
 pub use self::forest_node_content::ForestNodeContent;
 mod forest_node_rc;
 pub use self::forest_node_rc::{ForestNodeRc, ForestNodeWeak, ForestNodeRef, ForestNodeRefMut, ForestNodePtr};
 mod forest_node_self;
 pub use self::forest_node_self::ForestNodeSelf;
+mod forest_node_children;
+pub use self::forest_node_children::{ForestChildStorage, ForestNodeChildrenIter};
+use self::forest_node_children::ForestNodeChildren;
+mod forest_node_check;
+pub use self::forest_node_check::ForestInvariantError;
+mod forest_notification;
+use self::forest_notification::ForestNotification;
+mod forest_mutation_observer;
+pub use self::forest_mutation_observer::{ForestMutationObserver, MutationRecord, MutationRecordKind};
+use self::forest_mutation_observer::{ForestObserverInner, ForestObserverWeak};
+mod forest_event;
+pub use self::forest_event::{ForestEvent, ForestEventHandler, ForestEventPhase};
+mod forest_undo;
+pub use self::forest_undo::ForestUndoStack;
+use self::forest_undo::{ForestUndoGroup, ForestUndoInner, ForestUndoOp, ForestUndoWeak};
+mod forest_transaction;
+mod forest_snapshot;
+pub use self::forest_snapshot::ForestSnapshot;
+mod forest_dirty;
+mod forest_aggregate;
+pub use self::forest_aggregate::ForestAggregate;
+mod forest_node_map;
+pub use self::forest_node_map::ForestNodeMap;
+mod forest_update;
+mod forest_index;
+pub use self::forest_index::ForestIndex;
+use self::forest_index::{ForestIndexDyn, ForestIndexWeak};
+mod forest_inherited;
+pub use self::forest_inherited::ForestInherited;
+use self::forest_inherited::{ForestInheritedCache, ForestInheritedWeak};
 mod forest_context;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod forest_leak;
+pub use self::forest_leak::ForestLeakedCycle;
 use self::forest_context::{ForestContext, ForestContextRef, ForestContextRefMut};
 
 pub struct Forest<T: ForestNodeContent> {
-    context: Rc<ForestContext>,
+    context: Rc<ForestContext<T>>,
     phantom_data: PhantomData<T>,
 }
 
@@ -24,9 +58,972 @@ impl<T: ForestNodeContent> Forest<T> {
             phantom_data: PhantomData,
         }
     }
+    pub fn set_child_storage(&mut self, storage: ForestChildStorage) {
+        ForestContext::borrow_mut(&self.context);
+        self.context.set_child_storage(storage);
+    }
+    #[inline]
+    pub fn node_count(&self) -> usize {
+        self.context.node_count()
+    }
+    pub fn enable_node_registry(&mut self) {
+        ForestContext::borrow_mut(&self.context);
+        self.context.enable_registry();
+    }
+    #[inline]
+    pub fn has_node_registry(&self) -> bool {
+        self.context.has_registry()
+    }
+    pub fn all_nodes(&self) -> Vec<ForestNodeRc<T>> {
+        self.context.registered_nodes()
+    }
+    pub fn new_root(&mut self, content: T) -> ForestNodeRc<T> {
+        let node = ForestNodeRc::new(self, content);
+        self.add_root(node.clone());
+        node
+    }
+    /// A root that is later inserted as a child of another node stops being a root,
+    /// and is not added back when it is removed from that parent.
+    pub fn add_root(&mut self, node: ForestNodeRc<T>) -> bool {
+        let _status = ForestContext::borrow_mut(&self.context);
+        let n = unsafe { node.forest_node_mut() };
+        if !Rc::ptr_eq(n.context(), &self.context) {
+            panic!("A root node should be in the same Forest");
+        }
+        if n.has_parent() {
+            panic!("A root node should not have a parent");
+        }
+        let ret = n.set_root(true);
+        n.flush_notifications();
+        ret
+    }
+    pub fn remove_root(&mut self, node: &ForestNodeRc<T>) -> Option<ForestNodeRc<T>> {
+        let _status = ForestContext::borrow_mut(&self.context);
+        let n = unsafe { node.forest_node_mut() };
+        if !Rc::ptr_eq(n.context(), &self.context) || !n.set_root(false) { return None }
+        n.flush_notifications();
+        Some(node.clone())
+    }
+    pub fn roots(&self) -> Vec<ForestNodeRc<T>> {
+        self.context.roots()
+    }
+    pub fn set_deferred_notifications(&mut self, deferred: bool) {
+        ForestContext::borrow_mut(&self.context);
+        self.context.set_deferred(deferred);
+    }
+}
+
+impl<T: ForestNodeContent> Drop for Forest<T> {
+    fn drop(&mut self) {
+        drop(self.context.take_updates());
+        let status = match ForestContext::try_borrow_mut(&self.context) {
+            Ok(status) => status,
+            // panicking while unwinding would abort, so the roots stay connected
+            Err(_) if std::thread::panicking() => return,
+            Err(_) => panic!("Forest has been borrowed"),
+        };
+        self.context.flush_notifications();
+        self.context.set_deferred(false);
+        let roots = self.context.take_roots();
+        for root in roots.iter() {
+            unsafe { root.forest_node_mut() }.update_connected(false);
+        }
+        // the roots may be dropped now, which needs the forest unborrowed
+        drop(status);
+    }
+}
+
+impl<T: ForestNodeContent> Default for Forest<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // FIXME
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct Content(usize);
+    impl ForestNodeContent for Content { }
+
+    fn content_children(root: &mut ForestNode<Content>, count: usize, attached: usize) -> Vec<ForestNodeRc<Content>> {
+        let n: Vec<_> = (1..=count).map(|i| root.create_another(Content(i))).collect();
+        root.splice(0, 0, n[..attached].to_vec());
+        n
+    }
+
+    fn shape(root: &ForestNode<Content>) -> String {
+        let children: Vec<_> = root.iter_children().map(shape).collect();
+        format!("{}[{}]", root.0, children.join(","))
+    }
+
+    #[test]
+    fn chunked_children() {
+        let mut forest: Forest<Content> = Forest::new();
+        forest.set_child_storage(ForestChildStorage::Chunked);
+        let root = ForestNodeRc::new(&mut forest, Content(0));
+        let mut root = root.borrow_mut();
+        let mut expected = vec![];
+        for i in 0..6000 {
+            let child = root.create_another(Content(i));
+            let pos = (i * 7919) % (expected.len() + 1);
+            root.insert(child, pos);
+            expected.insert(pos, i);
+        }
+        for i in 0..4000 {
+            let pos = (i * 104729) % expected.len();
+            let child = root.remove(pos);
+            assert_eq!(root.another(&child).0, expected.remove(pos));
+        }
+        let inserts = (0..10).map(|i| root.create_another(Content(10000 + i))).collect();
+        root.splice(500, 20, inserts);
+        expected.splice(500..520, 10000..10010);
+        assert_eq!(root.len(), expected.len());
+        assert_eq!(root.iter_children().map(|x| x.0).collect::<Vec<_>>(), expected);
+        assert_eq!(root[1234].0, expected[1234]);
+        assert_eq!(root.check_invariants(), Ok(()));
+        assert!(root.iter().as_slice().is_none());
+        let mut forest: Forest<Content> = Forest::new();
+        let root = ForestNodeRc::new(&mut forest, Content(0));
+        let mut root = root.borrow_mut();
+        let child = root.create_another(Content(1));
+        root.append(child.clone());
+        assert!(ForestNodeRc::ptr_eq(&root.iter().as_slice().unwrap()[0], &child));
+    }
+
+    #[test]
+    fn reorder_within_parent() {
+        for storage in [ForestChildStorage::Vec, ForestChildStorage::Chunked] {
+            let mut forest: Forest<Content> = Forest::new();
+            forest.set_child_storage(storage);
+            let root = ForestNodeRc::new(&mut forest, Content(0));
+            let mut root = root.borrow_mut();
+            let n: Vec<_> = (0..200).map(|i| root.create_another(Content(i))).collect();
+            let values = |root: &ForestNode<Content>| root.iter_children().map(|x| x.0).collect::<Vec<_>>();
+            root.splice(0, 0, n[..3].to_vec());
+            assert!(ForestNodeRc::ptr_eq(&root.replace(n[1].clone(), 1), &n[1]));
+            assert_eq!(values(&root), vec![0, 1, 2]);
+            root.insert(n[2].clone(), 3);
+            assert_eq!(values(&root), vec![0, 1, 2]);
+            root.insert(n[2].clone(), 0);
+            assert_eq!(values(&root), vec![2, 0, 1]);
+            root.insert(n[2].clone(), 2);
+            assert_eq!(values(&root), vec![0, 2, 1]);
+            root.append(n[0].clone());
+            assert_eq!(values(&root), vec![2, 1, 0]);
+            assert_eq!(root.splice(0, 3, vec![n[1].clone()]).len(), 3);
+            assert_eq!(values(&root), vec![1]);
+            assert!(root.another(&n[0]).parent().is_none());
+            root.splice(1, 0, vec![n[3].clone(), n[4].clone(), n[3].clone(), n[1].clone()]);
+            assert_eq!(values(&root), vec![4, 3, 1]);
+            root.splice(0, 3, n.clone());
+            let mut expected: Vec<usize> = (0..200).collect();
+            for i in 0..400 {
+                let from = (i * 7919) % expected.len();
+                let to = (i * 104729) % (expected.len() + 1);
+                let child = root.child(from).unwrap().rc();
+                root.insert(child, to);
+                let x = expected.remove(from);
+                expected.insert(if to > from { to - 1 } else { to }, x);
+            }
+            assert_eq!(values(&root), expected);
+            let moved: Vec<_> = (0..50).map(|i| root.child(i * 3).unwrap().rc()).collect();
+            let values_moved: Vec<_> = (0..50).map(|i| expected[i * 3]).collect();
+            assert_eq!(root.splice(100, 40, moved).len(), 40);
+            expected.retain(|x| !values_moved.contains(x));
+            expected.splice(66..93, values_moved);
+            assert_eq!(values(&root), expected);
+            assert_eq!(root.check_invariants(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn invariants_after_splice() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let mut root = root.borrow_mut();
+        let a = root.create_another(Content(1));
+        let b = root.create_another(Content(2));
+        let children: Vec<_> = (0..10).map(|i| root.create_another(Content(10 + i))).collect();
+        root.append(a.clone());
+        root.append(b.clone());
+        root.another_mut(&a).splice(0, 0, children[0..6].to_vec());
+        root.another_mut(&b).splice(0, 0, children[4..10].to_vec());
+        root.another_mut(&a).splice(1, 2, children[8..10].to_vec());
+        assert_eq!(root.check_invariants(), Ok(()));
+        assert_eq!(root.another(&a).iter_children().map(|x| x.0).collect::<Vec<_>>(), vec![10, 18, 19, 13]);
+        assert_eq!(root.another(&b).iter_children().map(|x| x.0).collect::<Vec<_>>(), vec![14, 15, 16, 17]);
+    }
+
+    #[test]
+    fn model_test() {
+        for seed in 1..5 {
+            let mut forest: Forest<Content> = Forest::new();
+            testing::ModelTest { seed, ..Default::default() }.run(&mut forest, Content);
+            let mut forest: Forest<Content> = Forest::new();
+            forest.set_child_storage(ForestChildStorage::Chunked);
+            testing::ModelTest { seed, ..Default::default() }.run(&mut forest, Content);
+            let mut forest: Forest<Picky> = Forest::new();
+            forest.set_child_storage(ForestChildStorage::Chunked);
+            testing::ModelTest { seed, ..Default::default() }.run(&mut forest, Picky);
+        }
+    }
+
+    struct Picky(usize);
+    impl ForestNodeContent for Picky {
+        fn can_accept_child(&self, child: &Self) -> bool {
+            !(self.0 + child.0).is_multiple_of(3)
+        }
+    }
+
+    #[test]
+    fn node_count_and_registry() {
+        let mut forest: Forest<Content> = Forest::new();
+        forest.enable_node_registry();
+        let root = ForestNodeRc::new(&mut forest, Content(0));
+        {
+            let mut root = root.borrow_mut();
+            let child = root.create_another(Content(1));
+            root.append(child.clone());
+            let c = root.another_mut(&child);
+            let grand_child = c.create_another(Content(2));
+            c.append(grand_child);
+            root.create_another(Content(3));
+        }
+        assert_eq!(forest.node_count(), 3);
+        let mut all: Vec<usize> = forest.all_nodes().iter().map(|x| x.borrow().0).collect();
+        all.sort();
+        assert_eq!(all, vec![0, 1, 2]);
+        drop(root);
+        assert_eq!(forest.node_count(), 0);
+        assert!(forest.all_nodes().is_empty());
+    }
+
+    #[test]
+    fn forest_roots() {
+        let mut forest: Forest<Content> = Forest::new();
+        let a = forest.new_root(Content(0));
+        let b = forest.new_root(Content(1));
+        assert_eq!(forest.roots().len(), 2);
+        assert!(a.borrow().is_root());
+        a.borrow_mut().append(b.clone());
+        assert!(!b.borrow().is_root());
+        assert_eq!(forest.roots().len(), 1);
+        drop(b);
+        let b = a.borrow_mut().remove(0);
+        assert_eq!(forest.roots().len(), 1);
+        assert!(forest.add_root(b.clone()));
+        assert!(!forest.add_root(b.clone()));
+        drop(a);
+        assert_eq!(forest.node_count(), 2);
+        assert!(forest.remove_root(&b).is_some());
+        drop(b);
+        assert_eq!(forest.node_count(), 1);
+        drop(forest);
+    }
+
+    struct Logged {
+        id: usize,
+        log: Rc<std::cell::RefCell<Vec<String>>>,
+    }
+    impl Logged {
+        fn log(&self, s: String) {
+            self.log.borrow_mut().push(format!("{}: {}", self.id, s));
+        }
+    }
+    impl ForestNodeContent for Logged {
+        fn parent_node_changed_with(&mut self, old_parent: Option<ForestNodeWeak<Self>>, new_parent: Option<ForestNodeWeak<Self>>) {
+            let id = |x: Option<ForestNodeWeak<Self>>| x.and_then(|x| x.upgrade()).map(|x| unsafe { x.forest_node() }.id.to_string()).unwrap_or_else(|| "-".into());
+            self.log(format!("parent {} -> {}", id(old_parent), id(new_parent)));
+        }
+        fn child_inserted(&mut self, index: usize) {
+            self.log(format!("inserted {}", index));
+        }
+        fn child_removed(&mut self, index: usize) {
+            self.log(format!("removed {}", index));
+        }
+        fn connected(&mut self) {
+            self.log("connected".into());
+        }
+        fn disconnected(&mut self) {
+            self.log("disconnected".into());
+        }
+        fn update(node: &mut ForestNode<Self>) {
+            node.log("update".into());
+            if node.id == 1 {
+                for child in node.clone_children() {
+                    node.another_mut(&child).request_update();
+                }
+            }
+            if node.id == 3 {
+                node.parent_mut().unwrap().parent_mut().unwrap().request_update();
+            }
+        }
+        fn before_drop(node: &mut ForestNode<Self>) {
+            let children: Vec<_> = node.iter_children().map(|x| x.id.to_string()).collect();
+            node.log(format!("drop [{}]", children.join(", ")));
+        }
+    }
+
+    fn logged_nodes(forest: &mut Forest<Logged>, count: usize) -> (Vec<ForestNodeRc<Logged>>, Rc<std::cell::RefCell<Vec<String>>>) {
+        let log = Rc::new(std::cell::RefCell::new(vec![]));
+        let nodes = (0..count).map(|id| ForestNodeRc::new(forest, Logged { id, log: log.clone() })).collect();
+        (nodes, log)
+    }
+
+    #[test]
+    fn child_hooks() {
+        let mut forest: Forest<Logged> = Forest::new();
+        let (n, log) = logged_nodes(&mut forest, 5);
+        let mut root = n[0].borrow_mut();
+        root.append(n[1].clone());
+        root.append(n[2].clone());
+        root.another_mut(&n[1]).append(n[3].clone());
+        log.borrow_mut().clear();
+        root.splice(0, 1, vec![n[3].clone(), n[4].clone()]);
+        assert_eq!(*log.borrow(), vec!["1: removed 0", "0: removed 0", "0: inserted 0", "0: inserted 1", "1: parent 0 -> -", "3: parent 1 -> 0", "4: parent - -> 0"]);
+        log.borrow_mut().clear();
+        root.replace(n[1].clone(), 2);
+        assert_eq!(*log.borrow(), vec!["0: removed 2", "0: inserted 2", "2: parent 0 -> -", "1: parent - -> 0"]);
+    }
+
+    #[test]
+    fn connected_hooks() {
+        let mut forest: Forest<Logged> = Forest::new();
+        let (n, log) = logged_nodes(&mut forest, 5);
+        {
+            let mut a = n[1].borrow_mut();
+            a.append(n[2].clone());
+            a.append(n[3].clone());
+        }
+        assert!(forest.add_root(n[0].clone()));
+        log.borrow_mut().clear();
+        n[0].borrow_mut().append(n[1].clone());
+        assert_eq!(*log.borrow(), vec!["0: inserted 0", "1: parent - -> 0", "1: connected", "2: connected", "3: connected"]);
+        assert!(n[3].borrow().is_connected());
+        log.borrow_mut().clear();
+        {
+            let mut root = n[0].borrow_mut();
+            root.append(n[3].clone());
+            root.another_mut(&n[4]).append(n[1].clone());
+        }
+        assert_eq!(*log.borrow(), vec!["1: removed 1", "0: inserted 1", "3: parent 1 -> 0", "0: removed 0", "4: inserted 0", "1: parent 0 -> 4", "1: disconnected", "2: disconnected"]);
+        log.borrow_mut().clear();
+        forest.remove_root(&n[0]);
+        assert_eq!(*log.borrow(), vec!["0: disconnected", "3: disconnected"]);
+        forest.add_root(n[4].clone());
+        log.borrow_mut().clear();
+        drop(forest);
+        assert_eq!(*log.borrow(), vec!["4: disconnected", "1: disconnected", "2: disconnected"]);
+    }
+
+    #[test]
+    fn deferred_hooks() {
+        let mut forest: Forest<Logged> = Forest::new();
+        forest.set_deferred_notifications(true);
+        let (n, log) = logged_nodes(&mut forest, 4);
+        n[1].borrow_mut().append(n[3].clone());
+        log.borrow_mut().clear();
+        {
+            let mut root = n[0].borrow_mut();
+            root.splice(0, 0, vec![n[2].clone(), n[3].clone()]);
+            assert!(log.borrow().is_empty());
+            root.flush_notifications();
+            assert_eq!(*log.borrow(), vec!["1: removed 0", "0: inserted 0", "0: inserted 1", "2: parent - -> 0", "3: parent 1 -> 0"]);
+            log.borrow_mut().clear();
+            root.remove(0);
+            assert!(log.borrow().is_empty());
+        }
+        assert_eq!(*log.borrow(), vec!["0: removed 0", "2: parent 0 -> -"]);
+        log.borrow_mut().clear();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut root = n[0].borrow_mut();
+            root.append(n[1].clone());
+            panic!("unwinding");
+        }));
+        assert!(result.is_err());
+        assert!(log.borrow().is_empty());
+        drop(n[0].borrow_mut());
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn mutation_observer() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let mut root = root.borrow_mut();
+        let n = content_children(&mut root, 5, 3);
+        let observer = root.observe(true);
+        let child_observer = root.another_mut(&n[0]).observe(false);
+        root.another_mut(&n[0]).append(n[3].clone());
+        root.another_mut(&n[0]).append(n[1].clone());
+        root.another_mut(&n[3]).mark_content_changed();
+        root.replace(n[4].clone(), 1);
+        let records = observer.take_records();
+        let ids = |v: &[ForestNodeRc<Content>]| v.iter().map(|x| root.another(x).0).collect::<Vec<_>>();
+        let summary: Vec<_> = records.iter().map(|r| {
+            (r.kind, root.another(&r.target).0, ids(&r.added_nodes), ids(&r.removed_nodes), r.previous_sibling.as_ref().map(|x| root.another(x).0), r.next_sibling.as_ref().map(|x| root.another(x).0))
+        }).collect();
+        assert_eq!(summary, vec![
+            (MutationRecordKind::ChildList, 1, vec![4], vec![], None, None),
+            (MutationRecordKind::ChildList, 0, vec![], vec![2], Some(1), Some(3)),
+            (MutationRecordKind::ChildList, 1, vec![2], vec![], Some(4), None),
+            (MutationRecordKind::Content, 4, vec![], vec![], None, None),
+            (MutationRecordKind::ChildList, 0, vec![5], vec![3], Some(1), None),
+        ]);
+        assert_eq!(child_observer.take_records().len(), 2);
+        assert!(observer.take_records().is_empty());
+    }
+
+    #[derive(PartialEq)]
+    enum Schema {
+        Table,
+        Row,
+        Cell,
+    }
+    impl ForestNodeContent for Schema {
+        fn can_accept_child(&self, child: &Self) -> bool {
+            match self {
+                Schema::Table => *child == Schema::Row,
+                Schema::Row => *child == Schema::Cell,
+                Schema::Cell => true,
+            }
+        }
+        fn can_be_child_of(&self, parent: &Self) -> bool {
+            *self != Schema::Row || *parent == Schema::Table
+        }
+    }
+
+    #[test]
+    fn veto_hooks() {
+        let mut forest: Forest<Schema> = Forest::new();
+        let table = forest.new_root(Schema::Table);
+        let mut table = table.borrow_mut();
+        let row = table.create_another(Schema::Row);
+        let cell = table.create_another(Schema::Cell);
+        let inner = table.create_another(Schema::Table);
+        assert_eq!(table.try_append(cell.clone()), Err(ForestChildRejection::RejectedByParent { index: 0 }));
+        assert_eq!(table.try_splice(0, 0, vec![row.clone(), cell.clone()]).err(), Some(ForestChildRejection::RejectedByParent { index: 1 }));
+        assert!(table.is_empty());
+        table.append(row.clone());
+        table.another_mut(&row).append(cell.clone());
+        assert_eq!(table.another_mut(&row).try_insert(inner.clone(), 0), Err(ForestChildRejection::RejectedByParent { index: 0 }));
+        table.another_mut(&cell).append(inner.clone());
+        let another_row = table.create_another(Schema::Row);
+        assert_eq!(table.another_mut(&cell).try_append(another_row), Err(ForestChildRejection::RejectedByChild { index: 0 }));
+        assert_eq!(table.another_mut(&row).try_replace(inner, 0).err(), Some(ForestChildRejection::RejectedByParent { index: 0 }));
+    }
+
+    #[test]
+    fn drop_hooks() {
+        let mut forest: Forest<Logged> = Forest::new();
+        let (mut n, log) = logged_nodes(&mut forest, 4);
+        {
+            let mut root = n[0].borrow_mut();
+            root.append(n[1].clone());
+            root.append(n[2].clone());
+            root.another_mut(&n[1]).append(n[3].clone());
+        }
+        let kept = n.remove(2);
+        let root = n.remove(0);
+        drop(n);
+        log.borrow_mut().clear();
+        let guard = kept.borrow();
+        drop(root);
+        assert!(log.borrow().is_empty());
+        assert!(guard.has_parent());
+        drop(guard);
+        drop(forest);
+        assert_eq!(*log.borrow(), vec!["0: drop [1, 2]", "2: parent 0 -> -", "1: drop [3]", "3: drop []"]);
+        assert!(!kept.borrow().has_parent());
+    }
+
+    struct Heir(usize, Option<ForestNodeRc<Heir>>);
+    impl ForestNodeContent for Heir {
+        fn before_drop(node: &mut ForestNode<Self>) {
+            if let Some(heir) = node.1.take() {
+                let children = node.splice(0, node.len(), vec![]);
+                node.another_mut(&heir).splice(0, 0, children.into_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn drop_hooks_mutate() {
+        let mut forest: Forest<Heir> = Forest::new();
+        let heir = forest.new_root(Heir(0, None));
+        let node = ForestNodeRc::new(&mut forest, Heir(1, Some(heir.clone())));
+        {
+            let mut node = node.borrow_mut();
+            for i in 2..4 {
+                let child = node.create_another(Heir(i, None));
+                node.append(child);
+            }
+        }
+        drop(node);
+        let heir = heir.borrow();
+        assert_eq!(heir.iter_children().map(|x| x.0).collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    struct Click {
+        stop_at: Option<(usize, ForestEventPhase)>,
+        prevent: bool,
+        visited: Vec<(usize, ForestEventPhase)>,
+    }
+    impl ForestEventHandler<Click> for Content {
+        fn handle_event(&mut self, event: &mut ForestEvent<Self, Click>) {
+            let phase = event.phase();
+            event.detail.visited.push((self.0, phase));
+            if event.detail.stop_at == Some((self.0, phase)) { event.stop_propagation() }
+            if event.detail.prevent && self.0 == 1 { event.prevent_default() }
+        }
+    }
+
+    #[test]
+    fn event_dispatch() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let mut root = root.borrow_mut();
+        let a = root.create_another(Content(1));
+        let b = root.create_another(Content(2));
+        root.append(a.clone());
+        root.another_mut(&a).append(b.clone());
+        let event = root.another_mut(&b).dispatch_event(Click { stop_at: None, prevent: false, visited: vec![] });
+        assert_eq!(event.detail.visited, vec![
+            (0, ForestEventPhase::Capture),
+            (1, ForestEventPhase::Capture),
+            (2, ForestEventPhase::Target),
+            (1, ForestEventPhase::Bubble),
+            (0, ForestEventPhase::Bubble),
+        ]);
+        assert!(!event.default_prevented());
+        let event = root.another_mut(&b).dispatch_event(Click { stop_at: Some((1, ForestEventPhase::Bubble)), prevent: true, visited: vec![] });
+        assert_eq!(event.detail.visited.len(), 4);
+        assert!(event.propagation_stopped());
+        assert!(event.default_prevented());
+        assert!(ForestNodeRc::ptr_eq(event.target(), &b));
+    }
+
+    struct Fragile(bool);
+    impl ForestNodeContent for Fragile {
+        fn connected(&mut self) {
+            if self.0 { panic!("fragile node connected") }
+        }
+    }
+
+    #[test]
+    fn undo_after_panicking_hook() {
+        let mut forest: Forest<Fragile> = Forest::new();
+        let undo = ForestUndoStack::new(&mut forest);
+        let root = forest.new_root(Fragile(false));
+        let a = ForestNodeRc::new(&mut forest, Fragile(true));
+        let b = ForestNodeRc::new(&mut forest, Fragile(false));
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| root.borrow_mut().append(a.clone())));
+        assert!(ret.is_err());
+        root.borrow_mut().append(b.clone());
+        let mut root = root.borrow_mut();
+        assert!(undo.undo(&mut root));
+        assert_eq!(root.len(), 1);
+        assert!(undo.undo(&mut root));
+        assert_eq!(root.len(), 0);
+        assert!(root.another(&a).parent().is_none());
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut forest: Forest<Content> = Forest::new();
+        let undo = ForestUndoStack::new(&mut forest);
+        let root = forest.new_root(Content(0));
+        let mut root = root.borrow_mut();
+        let n = content_children(&mut root, 5, 3);
+        let s1 = shape(&root);
+        undo.begin_group();
+        root.another_mut(&n[0]).append(n[2].clone());
+        root.another_mut(&n[2]).append(n[3].clone());
+        root.another_mut(&n[3]).set_content(Content(40));
+        undo.end_group();
+        let s2 = shape(&root);
+        assert_eq!(s2, "0[1[3[40[]]],2[]]");
+        root.replace(n[4].clone(), 0);
+        let s3 = shape(&root);
+        assert!(undo.undo(&mut root));
+        assert_eq!(shape(&root), s2);
+        assert!(undo.undo(&mut root));
+        assert_eq!(shape(&root), s1);
+        assert!(undo.redo(&mut root));
+        assert_eq!(shape(&root), s2);
+        assert!(undo.redo(&mut root));
+        assert_eq!(shape(&root), s3);
+        assert!(!undo.redo(&mut root));
+        assert!(undo.undo(&mut root));
+        root.remove(0);
+        assert!(!undo.can_redo());
+        assert_eq!(root.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let detached = ForestNodeRc::new(&mut forest, Content(9));
+        let mut root = root.borrow_mut();
+        let n = content_children(&mut root, 5, 3);
+        root.another_mut(&n[1]).append(n[3].clone());
+        root.another_mut(&n[4]).set_root(true);
+        let before = shape(&root);
+        let ret: Result<(), &str> = root.transaction(|tx| {
+            tx.another_mut(&n[0]).append(n[3].clone());
+            tx.splice(1, 1, vec![n[4].clone(), detached.clone()]);
+            let ret: Result<(), &str> = tx.transaction(|tx| {
+                tx.remove(0);
+                Ok(())
+            });
+            assert!(ret.is_ok());
+            Err("invalid")
+        });
+        assert_eq!(ret, Err("invalid"));
+        assert_eq!(shape(&root), before);
+        assert!(root.another(&n[4]).is_root());
+        assert!(!root.another(&detached).has_parent());
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            root.transaction(|tx| -> Result<(), ()> {
+                tx.remove(2);
+                tx.remove(5);
+                Ok(())
+            })
+        }));
+        assert!(ret.is_err());
+        assert_eq!(shape(&root), before);
+        assert_eq!(root.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let mut root = root.borrow_mut();
+        let n = content_children(&mut root, 4, 2);
+        root.another_mut(&n[0]).append(n[2].clone());
+        root.another_mut(&n[1]).append(n[3].clone());
+        let s1 = root.snapshot();
+        root.another_mut(&n[3]).0 = 40;
+        let s2 = root.snapshot();
+        assert_eq!(s1[1][0].0, 4);
+        assert_eq!(s2[1][0].0, 40);
+        assert!(ForestSnapshot::ptr_eq(&s1[0], &s2[0]));
+        assert!(!ForestSnapshot::ptr_eq(&s1[1], &s2[1]));
+        root.another_mut(&n[0]).remove(0);
+        let s3 = root.snapshot();
+        assert!(ForestSnapshot::ptr_eq(&s2[1], &s3[1]));
+        assert!(s3[0].is_empty());
+        assert_eq!(s1[0].len(), 1);
+    }
+
+    #[test]
+    fn dirty_tracking() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let mut root = root.borrow_mut();
+        let n = content_children(&mut root, 4, 2);
+        root.another_mut(&n[0]).append(n[2].clone());
+        let ids = |root: &mut ForestNode<Content>| {
+            let dirty = root.take_dirty_descendants();
+            dirty.iter().map(|x| root.another(x).0).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&mut root), vec![0, 1, 3, 2]);
+        assert_eq!(ids(&mut root), vec![]);
+        let revision = root.another(&n[0]).revision();
+        root.another_mut(&n[2]).mark_content_changed();
+        assert!(root.is_subtree_dirty() && !root.is_dirty());
+        root.another_mut(&n[1]).append(n[3].clone());
+        assert_eq!(root.another(&n[0]).revision(), revision);
+        assert_eq!(ids(&mut root), vec![3, 2, 4]);
+        root.another_mut(&n[1]).append(n[2].clone());
+        assert_eq!(root.another(&n[0]).revision(), revision + 1);
+        assert_eq!(ids(&mut root), vec![1, 2]);
+    }
+
+    impl ForestAggregate for Content {
+        type Summary = (usize, usize);
+        fn identity() -> Self::Summary {
+            (0, 0)
+        }
+        fn summarize(&self) -> Self::Summary {
+            (1, self.0)
+        }
+        fn combine(summary: &mut Self::Summary, other: &Self::Summary) {
+            summary.0 += other.0;
+            summary.1 = summary.1.max(other.1);
+        }
+    }
+
+    #[test]
+    fn aggregates() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let mut root = root.borrow_mut();
+        let n = content_children(&mut root, 5, 2);
+        root.another_mut(&n[0]).append(n[2].clone());
+        assert_eq!(root.aggregate(), (4, 3));
+        root.another_mut(&n[1]).splice(0, 0, n[3..5].to_vec());
+        assert_eq!(root.aggregate(), (6, 5));
+        assert_eq!(root.another(&n[0]).aggregate(), (2, 3));
+        root.another_mut(&n[2]).0 = 10;
+        assert!(root.aggregate.borrow().is_none());
+        assert!(root.another(&n[1]).aggregate.borrow().is_some());
+        assert_eq!(root.aggregate(), (6, 10));
+        root.remove(0);
+        assert_eq!(root.aggregate(), (4, 5));
+        root.another_mut(&n[4]).mark_content_changed();
+        assert!(root.aggregate.borrow().is_none());
+    }
+
+    struct Inherit {
+        own: Option<u32>,
+        resolved: u32,
+    }
+    impl ForestNodeContent for Inherit {
+        fn node_parent_changed(node: &mut ForestNode<Self>, _old_parent: Option<ForestNodeWeak<Self>>, _new_parent: Option<ForestNodeWeak<Self>>) {
+            let inherited = node.parent().map(|p| p.resolved).unwrap_or(0);
+            node.resolved = node.own.unwrap_or(inherited);
+        }
+    }
+
+    #[test]
+    fn node_hooks() {
+        let mut forest: Forest<Inherit> = Forest::new();
+        let root = forest.new_root(Inherit { own: Some(3), resolved: 3 });
+        let mut root = root.borrow_mut();
+        let a = root.create_another(Inherit { own: None, resolved: 0 });
+        root.append(a.clone());
+        assert_eq!(root.another(&a).resolved, 3);
+        root.remove(0);
+        assert_eq!(root.another(&a).resolved, 0);
+    }
+
+    #[test]
+    fn inherited_properties() {
+        let mut forest: Forest<Inherit> = Forest::new();
+        let theme = ForestInherited::new(&mut forest, 1, |x: &Inherit| x.own);
+        let root = forest.new_root(Inherit { own: None, resolved: 0 });
+        let mut root = root.borrow_mut();
+        let n: Vec<_> = (0..4).map(|_| root.create_another(Inherit { own: None, resolved: 0 })).collect();
+        root.append(n[0].clone());
+        root.another_mut(&n[0]).append(n[1].clone());
+        root.another_mut(&n[1]).append(n[2].clone());
+        root.another_mut(&n[3]).own = Some(7);
+        assert_eq!(theme.get(root.another(&n[2])), 1);
+        root.another_mut(&n[0]).own = Some(2);
+        assert_eq!(theme.get(root.another(&n[2])), 2);
+        root.another_mut(&n[0]).own = Some(3);
+        root.another_mut(&n[0]).mark_content_changed();
+        assert_eq!(theme.get(root.another(&n[2])), 3);
+        assert_eq!(theme.get(&root), 1);
+        root.another_mut(&n[3]).append(n[1].clone());
+        assert_eq!(theme.get(root.another(&n[2])), 7);
+        root.another_mut(&n[3]).remove(0);
+        assert_eq!(theme.get(root.another(&n[2])), 1);
+    }
+
+    #[test]
+    fn update_scheduler() {
+        let mut forest: Forest<Logged> = Forest::new();
+        let (n, log) = logged_nodes(&mut forest, 5);
+        forest.add_root(n[0].clone());
+        {
+            let mut root = n[0].borrow_mut();
+            root.append(n[1].clone());
+            root.append(n[2].clone());
+            root.another_mut(&n[1]).append(n[3].clone());
+            for i in [2, 4, 1] {
+                root.another_mut(&n[i]).request_update();
+            }
+            assert!(root.another(&n[4]).is_update_requested());
+        }
+        log.borrow_mut().clear();
+        assert_eq!(forest.run_updates(), 4);
+        assert_eq!(*log.borrow(), vec!["1: update", "3: update", "2: update", "0: update"]);
+        assert!(!n[4].borrow().is_update_requested());
+        log.borrow_mut().clear();
+        assert_eq!(forest.run_updates(), 0);
+        n[2].borrow_mut().request_update();
+        n[0].borrow_mut().remove(1);
+        assert_eq!(forest.run_updates(), 0);
+        assert!(log.borrow().iter().all(|x| !x.ends_with("update")));
+        let detached = ForestNodeRc::new(&mut forest, Logged { id: 5, log: log.clone() });
+        detached.borrow_mut().request_update();
+        let weak = detached.downgrade();
+        drop(detached);
+        assert!(weak.upgrade().is_none());
+    }
+
+    struct Reparent(usize, Rc<std::cell::RefCell<Vec<usize>>>);
+    impl ForestNodeContent for Reparent {
+        fn update(node: &mut ForestNode<Self>) {
+            node.1.borrow_mut().push(node.0);
+            if node.0 == 1 {
+                let parent = node.parent_mut().unwrap();
+                let (second, third) = (parent.child(1).unwrap().rc(), parent.child(2).unwrap().rc());
+                parent.another_mut(&third).append(second);
+            }
+        }
+    }
+
+    #[test]
+    fn update_order_after_moves() {
+        let mut forest: Forest<Reparent> = Forest::new();
+        let log = Rc::new(std::cell::RefCell::new(vec![]));
+        let root = forest.new_root(Reparent(0, log.clone()));
+        {
+            let mut root = root.borrow_mut();
+            for i in 1..4 {
+                let child = root.create_another(Reparent(i, log.clone()));
+                root.append(child.clone());
+                root.another_mut(&child).request_update();
+            }
+        }
+        assert_eq!(forest.run_updates(), 3);
+        assert_eq!(*log.borrow(), vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn secondary_index() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let n: Vec<_> = (1..5).map(|i| ForestNodeRc::new(&mut forest, Content(i))).collect();
+        root.borrow_mut().append(n[0].clone());
+        let odd = forest.add_index(|x: &Content| (x.0 % 2 == 1).then_some(x.0));
+        let halves = forest.add_index(|x: &Content| Some(x.0 / 2));
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&odd, &1).unwrap(), &n[0]));
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&halves, &0).unwrap(), &root));
+        // created but not yet attached nodes are not indexed
+        assert!(forest.lookup(&odd, &3).is_none());
+        {
+            let mut root = root.borrow_mut();
+            root.another_mut(&n[0]).append(n[2].clone());
+            root.append(n[1].clone());
+        }
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&odd, &3).unwrap(), &n[2]));
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&halves, &1).unwrap(), &n[2]));
+        n[2].borrow_mut().set_content(Content(7));
+        assert!(forest.lookup(&odd, &3).is_none());
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&odd, &7).unwrap(), &n[2]));
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&halves, &3).unwrap(), &n[2]));
+        root.borrow_mut().remove(0);
+        assert!(forest.lookup(&odd, &1).is_none());
+        assert!(forest.lookup(&odd, &7).is_none());
+        assert!(forest.lookup(&halves, &3).is_none());
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&halves, &1).unwrap(), &n[1]));
+        assert!(forest.lookup(&halves, &99).is_none());
+        drop(odd);
+        root.borrow_mut().append(n[0].clone());
+        assert!(ForestNodeRc::ptr_eq(&forest.lookup(&halves, &3).unwrap(), &n[2]));
+    }
+
+    #[test]
+    fn node_map() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let n: Vec<_> = (1..4).map(|i| ForestNodeRc::new(&mut forest, Content(i))).collect();
+        let mut map = ForestNodeMap::new();
+        map.insert(&root, "root");
+        for x in n.iter() {
+            map.insert(x, "child");
+        }
+        assert_eq!(map.insert(&n[0], "first"), Some("child"));
+        assert_eq!(map.get(&n[0]), Some(&"first"));
+        assert_eq!(map.remove(&n[1]), Some("child"));
+        assert!(!map.contains_key(&n[1]));
+        *map.get_mut(&root).unwrap() = "top";
+        let mut n = n;
+        n.pop();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.iter().count(), 2);
+        assert_eq!(map.remove_stale(), 1);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&root), Some(&"top"));
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn node_identity() {
+        let mut forest: Forest<Content> = Forest::new();
+        let root = forest.new_root(Content(0));
+        let a = ForestNodeRc::new(&mut forest, Content(1));
+        let b = ForestNodeRc::new(&mut forest, Content(1));
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+        let set: std::collections::HashSet<_> = vec![a.clone(), b.clone(), a.clone()].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        let ordered: std::collections::BTreeSet<_> = vec![b.clone(), a.clone(), root.clone()].into_iter().collect();
+        assert_eq!(ordered.len(), 3);
+        assert_eq!(a < b, a.as_ptr() < b.as_ptr());
+        assert!(format!("{:?}", a).contains("content: Content(1)"));
+        {
+            let _r = root.borrow_mut();
+            assert!(format!("{:?}", a).contains("<borrowed>"));
+        }
+        {
+            let _r = root.borrow();
+            assert!(format!("{:?}", a).contains("content: Content(1)"));
+        }
+        let (wa, wb) = (a.downgrade(), b.downgrade());
+        assert!(format!("{:?}", wa).starts_with("ForestNodeWeak { ptr: "));
+        drop(set);
+        drop(ordered);
+        drop(a);
+        drop(b);
+        assert!(wa.upgrade().is_none());
+        assert_eq!(wa, wa.clone());
+        assert_ne!(wa, wb);
+        let weak_set: std::collections::HashSet<_> = vec![wa.clone(), wb, wa].into_iter().collect();
+        assert_eq!(weak_set.len(), 2);
+    }
+
+    struct Holder(Option<ForestNodeRc<Holder>>);
+    impl ForestNodeContent for Holder {
+        fn trace_node_rcs(&self, f: &mut dyn FnMut(&ForestNodeRc<Self>)) {
+            if let Some(ref x) = self.0 { f(x) }
+        }
+    }
+
+    #[test]
+    fn leaked_cycles() {
+        let mut forest: Forest<Holder> = Forest::new();
+        forest.enable_node_registry();
+        let root = forest.new_root(Holder(None));
+        let a = ForestNodeRc::new(&mut forest, Holder(None));
+        let b = ForestNodeRc::new(&mut forest, Holder(Some(a.clone())));
+        let c = {
+            let mut a = a.borrow_mut();
+            a.append(b.clone());
+            let c = a.create_another(Holder(Some(root.clone())));
+            a.append(c.clone());
+            c
+        };
+        let kept = ForestNodeRc::new(&mut forest, Holder(None));
+        {
+            let mut kept = kept.borrow_mut();
+            let child = kept.create_another(Holder(None));
+            kept.append(child);
+        }
+        let looped = ForestNodeRc::new(&mut forest, Holder(None));
+        looped.borrow_mut().0 = Some(looped.clone());
+        assert!(forest.find_leaked_cycles().is_empty());
+        let weak = |x: ForestNodeRc<Holder>| x.downgrade();
+        let (a, b, c, looped) = (weak(a), weak(b), weak(c), weak(looped));
+        drop(root);
+        let mut leaked = forest.find_leaked_cycles();
+        leaked.sort_by_key(|x| x.cycle.len());
+        assert_eq!(leaked.len(), 2);
+        assert!(leaked[0].cycle.len() == 1 && looped.upgrade().unwrap() == leaked[0].cycle[0]);
+        assert!(leaked[0].dependents.is_empty());
+        let mut cycle = leaked[1].cycle.clone();
+        cycle.sort();
+        let mut expected = vec![a.upgrade().unwrap(), b.upgrade().unwrap()];
+        expected.sort();
+        assert!(cycle == expected);
+        assert!(leaked[1].dependents == vec![c.upgrade().unwrap()]);
+    }
 }
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use super::*;
+
+pub struct ModelTest {
+    pub seed: u64,
+    pub steps: usize,
+    pub max_nodes: usize,
+}
+
+impl Default for ModelTest {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            steps: 2000,
+            max_nodes: 160,
+        }
+    }
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+struct Model {
+    parent: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+}
+
+impl Model {
+    fn is_ancestor_or_self(&self, a: usize, mut b: usize) -> bool {
+        loop {
+            if a == b { return true }
+            match self.parent[b] {
+                None => return false,
+                Some(p) => b = p,
+            }
+        }
+    }
+    fn can_insert(&self, parent: usize, child: usize) -> bool {
+        !self.is_ancestor_or_self(child, parent)
+    }
+    fn detach(&mut self, child: usize) {
+        if let Some(p) = self.parent[child].take() {
+            let i = self.children[p].iter().position(|&x| x == child).unwrap();
+            self.children[p].remove(i);
+        }
+    }
+    fn splice(&mut self, parent: usize, position: usize, removes: usize, inserts: &[usize]) {
+        // the last occurrence of a repeated node wins
+        let inserts: Vec<usize> = inserts.iter().enumerate().filter(|&(i, c)| !inserts[(i + 1)..].contains(c)).map(|(_, &c)| c).collect();
+        // the new children go before the first following sibling that is not itself moved
+        let reference = self.children[parent][(position + removes)..].iter().find(|c| !inserts.contains(c)).cloned();
+        let removed: Vec<usize> = self.children[parent][position..(position + removes)].to_vec();
+        for c in removed {
+            self.detach(c);
+        }
+        for &c in inserts.iter() {
+            self.detach(c);
+        }
+        let index = match reference {
+            Some(r) => self.children[parent].iter().position(|&x| x == r).unwrap(),
+            None => self.children[parent].len(),
+        };
+        self.children[parent].splice(index..index, inserts.iter().cloned());
+        for &c in inserts.iter() {
+            self.parent[c] = Some(parent);
+        }
+    }
+}
+
+impl ModelTest {
+    pub fn run<T: ForestNodeContent, F: FnMut(usize) -> T>(&self, forest: &mut Forest<T>, mut create: F) {
+        let mut rng = Rng(self.seed.max(1));
+        let outcome = |accepted: bool| if accepted { "accepted" } else { "rejected" };
+        let first = ForestNodeRc::new(forest, create(0));
+        let mut access = first.borrow_mut();
+        let mut nodes = vec![first.clone()];
+        let mut model = Model {
+            parent: vec![None],
+            children: vec![vec![]],
+        };
+        for step in 0..self.steps {
+            // favour the first node so that it grows past a single chunk
+            let p = if rng.below(4) != 0 { 0 } else { rng.below(nodes.len()) };
+            let len = model.children[p].len();
+            let candidates: Vec<usize> = (0..nodes.len()).filter(|&c| model.can_insert(p, c)).collect();
+            let op = rng.below(6);
+            let desc = match op {
+                0 if nodes.len() < self.max_nodes => {
+                    let id = nodes.len();
+                    nodes.push(access.create_another(create(id)));
+                    model.parent.push(None);
+                    model.children.push(vec![]);
+                    format!("create {}", id)
+                },
+                1 if !candidates.is_empty() => {
+                    let c = candidates[rng.below(candidates.len())];
+                    let accepted = access.another_mut(&nodes[p]).try_append(nodes[c].clone()).is_ok();
+                    if accepted { model.splice(p, len, 0, &[c]) }
+                    format!("append {} to {} ({})", c, p, outcome(accepted))
+                },
+                2 if !candidates.is_empty() => {
+                    let c = candidates[rng.below(candidates.len())];
+                    let pos = rng.below(len + 1);
+                    let accepted = access.another_mut(&nodes[p]).try_insert(nodes[c].clone(), pos).is_ok();
+                    if accepted { model.splice(p, pos, 0, &[c]) }
+                    format!("insert {} into {} at {} ({})", c, p, pos, outcome(accepted))
+                },
+                3 if len > 0 => {
+                    let pos = rng.below(len);
+                    let removed = access.another_mut(&nodes[p]).remove(pos);
+                    if !ForestNodeRc::ptr_eq(&removed, &nodes[model.children[p][pos]]) {
+                        panic!("model test failed at step {} (seed {}): removed a wrong child", step, self.seed);
+                    }
+                    model.splice(p, pos, 1, &[]);
+                    format!("remove {} from {}", pos, p)
+                },
+                4 if len > 0 && !candidates.is_empty() => {
+                    let c = candidates[rng.below(candidates.len())];
+                    let pos = rng.below(len);
+                    let accepted = access.another_mut(&nodes[p]).try_replace(nodes[c].clone(), pos).is_ok();
+                    if accepted { model.splice(p, pos, 1, &[c]) }
+                    format!("replace {} in {} at {} ({})", c, p, pos, outcome(accepted))
+                },
+                5 => {
+                    let pos = rng.below(len + 1);
+                    let removes = rng.below((len - pos).min(4) + 1);
+                    let count = if candidates.is_empty() { 0 } else { rng.below(5) };
+                    let inserts: Vec<usize> = (0..count).map(|_| candidates[rng.below(candidates.len())]).collect();
+                    let rcs = inserts.iter().map(|&c| nodes[c].clone()).collect();
+                    let accepted = access.another_mut(&nodes[p]).try_splice(pos, removes, rcs).is_ok();
+                    if accepted { model.splice(p, pos, removes, &inserts) }
+                    format!("splice {:?} into {} at {} removing {} ({})", inserts, p, pos, removes, outcome(accepted))
+                },
+                _ => continue,
+            };
+            self.check(&access, &nodes, &model, step, &desc);
+        }
+    }
+
+    fn check<T: ForestNodeContent>(&self, access: &ForestNode<T>, nodes: &[ForestNodeRc<T>], model: &Model, step: usize, desc: &str) {
+        let fail = |msg: String| -> ! {
+            panic!("model test failed at step {} (seed {}) after {}: {}", step, self.seed, desc, msg)
+        };
+        let index: HashMap<ForestNodePtr<T>, usize> = nodes.iter().enumerate().map(|(i, x)| (x.as_ptr(), i)).collect();
+        for (i, rc) in nodes.iter().enumerate() {
+            let n = access.another(rc);
+            let children: Vec<usize> = n.iter().map(|c| index[&c.as_ptr()]).collect();
+            if children != model.children[i] {
+                fail(format!("children of {} are {:?}, expected {:?}", i, children, model.children[i]));
+            }
+            let parent = n.parent().map(|p| index[&p.rc().as_ptr()]);
+            if parent != model.parent[i] {
+                fail(format!("parent of {} is {:?}, expected {:?}", i, parent, model.parent[i]));
+            }
+            if parent.is_none() {
+                if let Err(e) = n.check_invariants() {
+                    fail(format!("{}", e));
+                }
+            }
+        }
+    }
+}